@@ -1,355 +1,5293 @@
+use arboard::Clipboard;
+use chrono::{Local, NaiveDate, NaiveDateTime, Timelike};
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind, KeyModifiers,
+    },
     execute,
+    style::Print,
     terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
 };
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use std::collections::{BTreeMap, HashSet, VecDeque};
 use std::error::Error;
 use std::io::stdout;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Instant;
 use std::{fs, io, time::Duration};
-use tui::layout::{Constraint, Direction, Layout};
+use tui::buffer::Buffer;
+use tui::layout::{Constraint, Direction, Layout, Rect};
 use tui::style::{Color, Modifier, Style};
-use tui::text::Spans;
-use tui::widgets::{Block, Borders, List, ListItem, Paragraph};
+use tui::text::{Span, Spans};
+use tui::widgets::{
+    Block, Borders, Cell, Clear, List, ListItem, ListState, Paragraph, Row, Sparkline, Table,
+    TableState, Widget, Wrap,
+};
 use tui::{Terminal, backend::CrosstermBackend};
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-enum Status {
-    Pending,
-    Working,
-    Done,
+mod tasks;
+mod tcr;
+
+use tasks::{
+    Document, FileFormat, Filter, Frontmatter, Headers, INDENT, ISSUE_TRACKER, IssueTracker,
+    Priority, Region, Side, Status, Task, assign_ids, conflict_regions, export_to_json,
+    fix_nesting, format_minutes, insert_sibling, invalid_due, load_document, load_tasks, next_id,
+    parse_task_line, render_tasks, resolve_regions, subtask_progress, subtree, write_atomically,
+    write_tasks_file,
+};
+use tcr::{
+    Git, Jujutsu, Mercurial, Multiplexer, TCR_LOG_LIMIT, TCR_PREFIX, TCRIGNORE_FILE, TcrCommit,
+    TestJob, TestRun, Vcs, Verdict, amend_refusal, commit_message, count_tcr_commits, diff_stat,
+    in_repo, init_repo, last_commit_time, load_tcrignore, repo_root, root_pattern,
+    run_test_command, tcr_log,
+};
+
+/// A task list change that `.` applies again to the selected task.
+#[derive(Debug, Clone, PartialEq)]
+enum Repeat {
+    Delete,
+    Duplicate,
+    /// The status a toggle moved to: `.` sets it rather than cycling on.
+    SetStatus(Status),
+    /// Whether `*` flagged or cleared the review flag.
+    Review(bool),
+}
+
+impl Repeat {
+    /// The key whose arm carries the change out again.
+    fn key(&self) -> KeyCode {
+        match self {
+            Repeat::Delete => KeyCode::Char('d'),
+            Repeat::Duplicate => KeyCode::Char('y'),
+            Repeat::SetStatus(_) => KeyCode::Char(' '),
+            Repeat::Review(_) => KeyCode::Char('*'),
+        }
+    }
+
+    fn label(&self) -> String {
+        match self {
+            Repeat::Delete => "delete".to_string(),
+            Repeat::Duplicate => "duplicate".to_string(),
+            Repeat::SetStatus(status) => format!("set {status:?}"),
+            Repeat::Review(true) => "flag for review".to_string(),
+            Repeat::Review(false) => "clear review flag".to_string(),
+        }
+    }
+}
+
+/// A deleted task with its subtasks, as the trash (`X`) keeps it.
+#[derive(Debug)]
+struct Deleted {
+    /// The task first, then its subtasks, all at the depth they had.
+    tasks: Vec<Task>,
+    /// Where the task stood in the list.
+    index: usize,
+    /// Id of the task right above it, to go back under even after the
+    /// list has changed. `None` when it was at the top.
+    after: Option<u64>,
+}
+
+/// Order of the task list on screen; the file always keeps its own order.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Sort {
+    #[default]
+    File,
+    Due,
+    Priority,
+}
+
+impl Sort {
+    fn next(self) -> Sort {
+        match self {
+            Sort::File => Sort::Due,
+            Sort::Due => Sort::Priority,
+            Sort::Priority => Sort::File,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Sort::File => "file order",
+            Sort::Due => "due date",
+            Sort::Priority => "priority",
+        }
+    }
+
+    /// Reorders `visible` (indices into `tasks`), keeping file order among equals.
+    fn apply(self, tasks: &[Task], visible: &mut [usize]) {
+        match self {
+            Sort::File => {}
+            Sort::Due => visible.sort_by_key(|&i| (tasks[i].due.is_none(), tasks[i].due)),
+            Sort::Priority => visible.sort_by_key(|&i| {
+                (
+                    tasks[i].priority.is_none(),
+                    std::cmp::Reverse(tasks[i].priority),
+                )
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct Config {
+    /// The Markdown file holding the task list.
+    tasks_file: String,
+    /// What a start without `--markdown` opens: `"file"` (`tasks_file`),
+    /// `"last"` (the file opened last time) or `"picker"` (a choice of the
+    /// recently opened files).
+    startup: String,
+    /// Show tasks that are due today or overdue when the app starts.
+    due_banner: bool,
+    /// On the first start of a day, greet with yesterday's completions and
+    /// offer to roll Working tasks over to today and restart `#daily` tasks.
+    daily_rollover: bool,
+    /// Moving past the last task jumps to the first one and vice versa.
+    wrap_navigation: bool,
+    /// Point out trailing whitespace, double spaces and all-caps descriptions
+    /// after adding or editing a task. The task is saved either way.
+    format_warnings: bool,
+    /// Flash a short animation in the status line when a TCR pass completes a
+    /// task or the last task is marked Done.
+    celebrate: bool,
+    /// The input box length counter turns red past this many characters; 0 keeps it grey.
+    input_length_warning: usize,
+    /// What Enter does in the task list: `"toggle"` the status (Space always
+    /// does), `"edit"` the task or show its `"detail"`s.
+    enter_action: String,
+    /// Keep the add box open after `a` adds a task, so several can be typed in
+    /// a row; Esc closes it. Off goes back to the list after each task.
+    multi_add: bool,
+    /// Milliseconds to wait for a key before redrawing. Longer saves power
+    /// but updates timers, fading messages and reminders less often.
+    poll_interval_ms: u64,
+    /// TCR commit subjects longer than this are cut at a word and continue
+    /// in the commit body; 0 keeps the whole message on the subject line.
+    commit_subject_length: usize,
+    /// Ask for a one-line "what I did" note when a task is marked Done or a
+    /// TCR cycle completes it; the note is kept on the task and added to the
+    /// body of the TCR commit.
+    done_note: bool,
+    /// Pass `--no-verify` to `git commit`, skipping pre-commit and commit-msg hooks.
+    no_verify: bool,
+    /// Ask before `t` runs the tests, showing the command, for suites too slow
+    /// to start by accident.
+    confirm_test: bool,
+    /// Inside tmux or WezTerm, run `t` in a new pane below the app instead of
+    /// leaving the UI for the duration of the tests.
+    test_pane: bool,
+    /// What commits and reverts: `"git"`, `"jj"` (Jujutsu) or `"hg"` (Mercurial).
+    /// The TCR log and statistics read git history only.
+    vcs: String,
+    /// When a passing `t` commits: `"green"` on any passing run, `"done"` only
+    /// when it completes the selected task, which it then marks Done.
+    commit_policy: String,
+    /// Leave the tasks file alone when failing tests revert the working tree,
+    /// so status changes made before `t` survive the revert.
+    revert_keeps_tasks_file: bool,
+    /// Replace emoji with ASCII in the UI and the file headings (also `--ascii`),
+    /// for terminals and fonts that render them poorly.
+    ascii: bool,
+    /// Draw without colors, marking the selection with `>` (also `--plain`,
+    /// or a non-empty `NO_COLOR` in the environment). Ctrl+p switches for the session.
+    plain: bool,
+    headers: Headers,
+    view: ViewSettings,
+    theme: Theme,
+    /// Show due dates as "in 3 days" / "2 days overdue" instead of `2024-07-01`.
+    relative_dates: bool,
+    /// Blank lines left between tasks in the list view (`=` switches 0 and 1).
+    item_spacing: u16,
+    /// Collapse tasks that are exact duplicates (e.g. after a bad merge) when loading.
+    dedup_on_load: bool,
+    /// Commit the task list after it changes, separately from test-gated TCR commits.
+    commit_on_change: bool,
+    /// Changes made within this many seconds of each other go into one commit.
+    commit_debounce_secs: u64,
+    /// How many deleted tasks the trash (`X`) keeps.
+    trash_size: usize,
+    /// Keep the trash in `.trash.md` so deleted tasks survive a restart.
+    persist_trash: bool,
+    /// Issue tracker URL that issue numbers are appended to, e.g.
+    /// `https://github.com/org/repo/issues/`; `i` opens the link.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    issue_url: Option<String>,
+    /// Regex for the words that are issue references rather than tags; its
+    /// first group is the issue number. Only used with `issue_url`.
+    issue_pattern: String,
+    /// Command used by Ctrl+o to open URLs instead of the system default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url_opener: Option<String>,
+    /// The opener runs inside the terminal (e.g. `w3m`), so the UI is suspended while it runs.
+    url_opener_in_terminal: bool,
+    /// What `Y` copies: `"plain"` for the description, `"markdown"` for the whole checkbox line.
+    copy_format: String,
+    /// Remind to run `t` once the last commit is this many minutes old; 0 disables it.
+    idle_reminder_minutes: u64,
+    /// `"bell"` rings the terminal bell along with the reminder, `"flash"` only shows it.
+    idle_reminder: String,
+    /// Show a legend of the status prefixes below the cheatsheet.
+    show_legend: bool,
+    /// The name at the start of the list's border title, e.g. the project;
+    /// empty uses the frontmatter's `project`, or else "Tasks".
+    list_title: String,
+    /// Copy the tasks file to `<tasks_file>.bak` before an import replaces the list.
+    backup_on_import: bool,
+    /// Add an `ord:` token to every task so reloading restores the list order across sections.
+    keep_order: bool,
+    /// Write each section sorted by priority, due date and description instead of list order.
+    sort_on_save: bool,
+    /// Keep this many previous versions as `<tasks_file>.1` (newest) to `.N`; 0 disables it.
+    backups: usize,
+    /// Trailing lines of captured test output shown after a failing run.
+    output_lines: usize,
+    /// Show the whole captured output instead of the last `output_lines` (toggled with `f`).
+    full_output: bool,
+    /// A run whose output matches this regex fails even if it exits with 0.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fail_on_pattern: Option<String>,
+    /// A run only passes if its output matches this regex.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    require_pattern: Option<String>,
+    /// What exit codes of the test command mean, by code: `"pass"`, `"fail"`
+    /// or `"ignore"` (no commit and no revert). Unlisted codes pass only when 0.
+    exit_codes: BTreeMap<String, String>,
+    /// Frontmatter read from the top of the tasks file; written back by `save_tasks`.
+    #[serde(skip)]
+    frontmatter: Option<Frontmatter>,
+    /// Set by `--readonly`: nothing is saved or committed.
+    #[serde(skip)]
+    readonly: bool,
+    /// Set by `--ascii`: `ascii` for this run, without saving it.
+    #[serde(skip)]
+    ascii_flag: bool,
+    /// The file opened instead of `tasks_file` for this run, by `--markdown`
+    /// or `startup`.
+    #[serde(skip)]
+    open_file: Option<String>,
+    /// Set by `--markdown`: `open_file` is any Markdown file, not a tasks file.
+    #[serde(skip)]
+    markdown: bool,
+    /// The Markdown around the checkboxes of the `--markdown` file; `save_tasks`
+    /// writes the tasks back into it.
+    #[serde(skip)]
+    document: Option<Document>,
+    /// Set when `tasksmanager.toml` did not parse: the defaults are in use
+    /// and `save_config` leaves the file alone.
+    #[serde(skip)]
+    unreadable: bool,
+    /// Paths staged by the non-test commits (`c` and `commit_on_change`); empty means
+    /// just `tasks_file`. TCR commits always stage everything.
+    commit_paths: Vec<String>,
+    /// Longest description kept on the checkbox line; the rest moves to the
+    /// task's notes when it is added or edited. 0 means no limit.
+    max_description_length: usize,
+    /// Task templates for `N`, e.g. a "bug" template with `tags = ["bug"]` and `priority = "high"`.
+    templates: Vec<Template>,
+}
+
+/// Totals for the statistics view (`%`), gathered when it opens.
+struct Stats {
+    by_status: Vec<(Status, usize)>,
+    /// Mean days from the last move to Working until Done.
+    average_working_days: Option<f64>,
+    /// Tasks finished on each of the last seven days, oldest first.
+    done_per_day: Vec<u64>,
+    /// Commits made by passing TCR runs, if the log could be read.
+    tcr_commits: Option<usize>,
+}
+
+impl Stats {
+    fn collect(tasks: &[Task], today: NaiveDate) -> Self {
+        let by_status = Status::ALL
+            .iter()
+            .map(|status| {
+                let count = tasks.iter().filter(|t| &t.status == status).count();
+                (status.clone(), count)
+            })
+            .collect();
+        let spans: Vec<i64> = tasks
+            .iter()
+            .filter_map(|t| Some((t.done? - t.worked?).num_days()))
+            .filter(|days| *days >= 0)
+            .collect();
+        let average_working_days =
+            (!spans.is_empty()).then(|| spans.iter().sum::<i64>() as f64 / spans.len() as f64);
+        let done_per_day = (0..7)
+            .rev()
+            .map(|ago| {
+                let day = today - chrono::Duration::days(ago);
+                tasks.iter().filter(|t| t.done == Some(day)).count() as u64
+            })
+            .collect();
+        Stats {
+            by_status,
+            average_working_days,
+            done_per_day,
+            tcr_commits: count_tcr_commits(),
+        }
+    }
+}
+
+/// Session state carried between runs, kept in `STATE_FILE`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct State {
+    /// Id of the task that was selected when the app last quit.
+    last_selected: Option<u64>,
+    /// Id of the current task (`C`), which TCR commits are made for.
+    current_task: Option<u64>,
+    /// The tasks as they were when the app last quit, per tasks file, to
+    /// show what changed in the meantime.
+    snapshots: BTreeMap<String, Vec<TaskSnapshot>>,
+    /// Tasks files opened lately, the last one first, for `startup`.
+    recent_files: Vec<String>,
+    /// The day the app was last started, to notice the first start of a day.
+    last_opened: Option<NaiveDate>,
+}
+
+impl State {
+    /// Notes where the session stands, as saved when the app quits.
+    fn record(
+        &mut self,
+        snapshot_key: &str,
+        tasks: &[Task],
+        selected: usize,
+        current: Option<u64>,
+    ) {
+        self.last_selected = tasks.get(selected).map(|t| t.id);
+        self.current_task = current;
+        self.snapshots
+            .insert(snapshot_key.to_string(), TaskSnapshot::of(tasks));
+    }
+}
+
+/// What quitting does once the UI is done: the last `commit_on_change` commit
+/// and saving the session state. `run_app` keeps one up to date so that the
+/// hangup watchdog in `run` can carry it out when the loop is stuck.
+struct Teardown {
+    /// The VCS, paths and `no_verify` for an `Update task list` commit.
+    commit: Option<(Box<dyn Vcs>, Vec<String>, bool)>,
+    /// `None` under `--readonly`, which leaves the state file as it was.
+    state: Option<State>,
+}
+
+impl Teardown {
+    /// `changed` says whether the task list was saved since the last commit.
+    fn new(config: &Config, state: &State, changed: bool) -> Teardown {
+        Teardown {
+            commit: (config.commit_on_change && changed)
+                .then(|| (config.vcs(), config.commit_paths(), config.no_verify)),
+            state: (!config.readonly).then(|| state.clone()),
+        }
+    }
+
+    /// Returns a warning for what failed but is not worth failing over.
+    fn run(self) -> Result<Option<String>, Box<dyn Error>> {
+        let mut warning = None;
+        if let Some((vcs, paths, no_verify)) = self.commit
+            && let Err(e) = vcs.commit("Update task list", &paths, no_verify)
+        {
+            warning = Some(format!("the task list was saved but not committed: {e}"));
+        }
+        if let Some(state) = self.state {
+            save_state(&state)?;
+        }
+        Ok(warning)
+    }
+}
+
+/// What `changes_since` compares of a task between sessions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TaskSnapshot {
+    id: u64,
+    description: String,
+    status: Status,
+}
+
+impl TaskSnapshot {
+    fn of(tasks: &[Task]) -> Vec<TaskSnapshot> {
+        tasks
+            .iter()
+            .map(|task| TaskSnapshot {
+                id: task.id,
+                description: task.description.clone(),
+                status: task.status.clone(),
+            })
+            .collect()
+    }
+}
+
+/// The `[view]` section: the filter and sort last used, restored on startup.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct ViewSettings {
+    /// Query as typed after `/`, e.g. `working #docs`.
+    filter: String,
+    sort: Sort,
+    /// List the tasks under a heading per tag (`G`) instead of in file order.
+    group_by_tag: bool,
+}
+
+/// A `[[templates]]` entry, picked with `N` to pre-fill the input box.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Template {
+    name: String,
+    /// May hold `{placeholder}`s to fill in before the task is added, e.g. `"Fix {what}"`.
+    description: String,
+    /// Added as `#tag`s; the `#` is optional.
+    tags: Vec<String>,
+    /// `"high"`, `"medium"` or `"low"`; empty leaves the priority unset.
+    priority: String,
+}
+
+impl Template {
+    /// The task line the template expands to, as typed in the input box.
+    fn line(&self) -> String {
+        let mut line = self.description.clone();
+        for tag in &self.tags {
+            line.push_str(&format!(" #{}", tag.trim_start_matches('#')));
+        }
+        if let Some(priority) = Priority::parse(&self.priority) {
+            line.push_str(&format!(" pri:{}", priority.label()));
+        }
+        line.trim().to_string()
+    }
+}
+
+/// The first `{placeholder}` in `text` starting at or after char `from`, as
+/// a char range.
+fn next_placeholder(text: &str, from: usize) -> Option<std::ops::Range<usize>> {
+    let start = text.char_indices().nth(from).map_or(text.len(), |(i, _)| i);
+    PLACEHOLDER_PATTERN.find(&text[start..]).map(|m| {
+        let at = text[..start + m.start()].chars().count();
+        at..at + m.as_str().chars().count()
+    })
+}
+
+/// The `[theme]` section: colors of the status prefix by priority and the
+/// prefixes themselves. Names are tui colors such as `"red"` or `"darkgray"`,
+/// or `"#rrggbb"`; an empty or unknown name keeps the status color. An empty
+/// prefix keeps the default (`[ ]`, `[working]`, `[done]`). Prefixes only
+/// change the list; `tasks.md` keeps its `[ ]`, `[~]` and `[x]` markers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct Theme {
+    high: String,
+    medium: String,
+    low: String,
+    pending: String,
+    working: String,
+    done: String,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            high: "red".to_string(),
+            medium: String::new(),
+            low: "darkgray".to_string(),
+            pending: String::new(),
+            working: String::new(),
+            done: String::new(),
+        }
+    }
+}
+
+impl Theme {
+    /// The prefix shown before a task with `status` in the list.
+    fn prefix<'a>(&'a self, status: &Status) -> &'a str {
+        let custom = match status {
+            Status::Pending => &self.pending,
+            Status::Working => &self.working,
+            Status::Done => &self.done,
+        };
+        if custom.is_empty() {
+            status.label()
+        } else {
+            custom
+        }
+    }
+
+    /// A span per status showing its prefix in its color and what it means.
+    fn legend(&self) -> Vec<Span<'_>> {
+        let mut spans = Vec::new();
+        for status in Status::ALL {
+            spans.push(Span::styled(
+                self.prefix(&status),
+                Style::default().fg(status.color()),
+            ));
+            spans.push(Span::raw(format!(" {}   ", status.meaning())));
+        }
+        spans
+    }
+
+    fn prefix_color(&self, task: &Task) -> Color {
+        let name = match task.priority {
+            Some(Priority::High) => &self.high,
+            Some(Priority::Medium) => &self.medium,
+            Some(Priority::Low) => &self.low,
+            None => "",
+        };
+        parse_color(name).unwrap_or_else(|| task.status.color())
+    }
+}
+
+fn parse_color(name: &str) -> Option<Color> {
+    if let Some(hex) = name.strip_prefix('#')
+        && hex.len() == 6
+    {
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        return Some(Color::Rgb(channel(0)?, channel(2)?, channel(4)?));
+    }
+    let color = match name.to_lowercase().replace(['-', '_', ' '], "").as_str() {
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "gray" | "grey" => Color::Gray,
+        "darkgray" | "darkgrey" => Color::DarkGray,
+        "lightred" => Color::LightRed,
+        "lightgreen" => Color::LightGreen,
+        "lightyellow" => Color::LightYellow,
+        "lightblue" => Color::LightBlue,
+        "lightmagenta" => Color::LightMagenta,
+        "lightcyan" => Color::LightCyan,
+        "white" => Color::White,
+        _ => return None,
+    };
+    Some(color)
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            tasks_file: DEFAULT_TASKS_FILE.to_string(),
+            startup: "file".to_string(),
+            due_banner: true,
+            daily_rollover: true,
+            wrap_navigation: false,
+            enter_action: "toggle".to_string(),
+            multi_add: true,
+            input_length_warning: 72,
+            format_warnings: false,
+            celebrate: false,
+            poll_interval_ms: 100,
+            commit_subject_length: 72,
+            done_note: false,
+            no_verify: false,
+            confirm_test: false,
+            test_pane: false,
+            vcs: "git".to_string(),
+            commit_policy: "green".to_string(),
+            revert_keeps_tasks_file: false,
+            ascii: false,
+            plain: false,
+            headers: Headers::default(),
+            view: ViewSettings::default(),
+            theme: Theme::default(),
+            relative_dates: false,
+            item_spacing: 0,
+            dedup_on_load: false,
+            commit_on_change: false,
+            commit_debounce_secs: 5,
+            trash_size: 10,
+            persist_trash: false,
+            issue_url: None,
+            issue_pattern: r"#(\d+)".to_string(),
+            url_opener: None,
+            url_opener_in_terminal: false,
+            copy_format: "plain".to_string(),
+            idle_reminder_minutes: 0,
+            idle_reminder: "bell".to_string(),
+            show_legend: false,
+            list_title: String::new(),
+            backup_on_import: true,
+            backups: 0,
+            sort_on_save: false,
+            keep_order: false,
+            output_lines: 20,
+            full_output: false,
+            fail_on_pattern: None,
+            require_pattern: None,
+            exit_codes: BTreeMap::new(),
+            commit_paths: Vec::new(),
+            max_description_length: 0,
+            templates: Vec::new(),
+            frontmatter: None,
+            readonly: false,
+            ascii_flag: false,
+            open_file: None,
+            markdown: false,
+            document: None,
+            unreadable: false,
+        }
+    }
+}
+
+impl Config {
+    /// What a task list binding does, given that `enter_action` rebinds Enter.
+    fn view_action<'a>(&self, key: &str, action: &'a str) -> &'a str {
+        match (key, self.enter_action.as_str()) {
+            ("Enter", "edit") => "edit",
+            ("Enter", "detail") => "details",
+            _ => action,
+        }
+    }
+
+    /// The `[headers]` settings with the frontmatter's `project` and `theme` on
+    /// top, forced to the ascii preset by `ascii`.
+    fn headers(&self) -> Headers {
+        let mut headers = self.headers.clone();
+        if let Some(frontmatter) = &self.frontmatter {
+            if let Some(project) = frontmatter.get("project") {
+                headers.title = Some(project);
+            }
+            if let Some(theme) = frontmatter.get("theme") {
+                headers.preset = theme;
+            }
+        }
+        if self.ascii() {
+            headers.preset = "ascii".to_string();
+        }
+        headers
+    }
+
+    /// The list's border title: `list_title`, the frontmatter's `project` or
+    /// "Tasks", followed by the name of the open file.
+    fn list_title(&self) -> String {
+        let name = if !self.list_title.is_empty() {
+            self.list_title.clone()
+        } else {
+            self.frontmatter
+                .as_ref()
+                .and_then(|frontmatter| frontmatter.get("project"))
+                .unwrap_or_else(|| "Tasks".to_string())
+        };
+        let path = Path::new(self.tasks_file());
+        let file = path
+            .file_name()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy();
+        format!("{name} ({file})")
+    }
+
+    /// How the tasks file is written, from `[headers]`, the frontmatter,
+    /// `sort_on_save` and `keep_order`.
+    fn file_format(&self) -> FileFormat {
+        FileFormat {
+            headers: self.headers(),
+            frontmatter: self.frontmatter.clone(),
+            sort_on_save: self.sort_on_save,
+            keep_order: self.keep_order,
+        }
+    }
+
+    /// Whether emoji give way to ASCII, by the config or `--ascii`.
+    fn ascii(&self) -> bool {
+        self.ascii || self.ascii_flag
+    }
+
+    fn vcs(&self) -> Box<dyn Vcs> {
+        match self.vcs.as_str() {
+            "jj" => Box::new(Jujutsu),
+            "hg" => Box::new(Mercurial),
+            _ => Box::new(Git),
+        }
+    }
+
+    /// The file the tasks are read from and saved to.
+    fn tasks_file(&self) -> &str {
+        self.open_file.as_deref().unwrap_or(&self.tasks_file)
+    }
+
+    fn commit_paths(&self) -> Vec<String> {
+        if self.commit_paths.is_empty() {
+            vec![self.tasks_file().to_string()]
+        } else {
+            self.commit_paths.clone()
+        }
+    }
+}
+
+/// The text being typed in an input box and the cursor position within it,
+/// counted in characters.
+#[derive(Debug, Default)]
+struct InputBuffer {
+    text: String,
+    cursor: usize,
+}
+
+impl InputBuffer {
+    /// Replaces the text, placing the cursor at its end or at its start.
+    fn set(&mut self, text: String, cursor_at_end: bool) {
+        self.cursor = if cursor_at_end {
+            text.chars().count()
+        } else {
+            0
+        };
+        self.text = text;
+    }
+
+    fn clear(&mut self) {
+        self.set(String::new(), true);
+    }
+
+    fn take(&mut self) -> String {
+        self.cursor = 0;
+        std::mem::take(&mut self.text)
+    }
+
+    fn byte_index(&self, cursor: usize) -> usize {
+        self.text
+            .char_indices()
+            .nth(cursor)
+            .map_or(self.text.len(), |(i, _)| i)
+    }
+
+    /// Deletes the word before the cursor, with any spaces between it and
+    /// the cursor, as readline's Ctrl+w does.
+    fn delete_word(&mut self) {
+        let before: Vec<char> = self.text.chars().take(self.cursor).collect();
+        let spaces = before
+            .iter()
+            .rev()
+            .take_while(|c| c.is_whitespace())
+            .count();
+        let word = before[..before.len() - spaces]
+            .iter()
+            .rev()
+            .take_while(|c| !c.is_whitespace())
+            .count();
+        let start = self.cursor - spaces - word;
+        let bytes = self.byte_index(start)..self.byte_index(self.cursor);
+        self.text.replace_range(bytes, "");
+        self.cursor = start;
+    }
+
+    /// Applies a text-editing key; other keys are ignored.
+    fn handle_key(&mut self, code: KeyCode) {
+        let len = self.text.chars().count();
+        match code {
+            KeyCode::Char(c) => {
+                let at = self.byte_index(self.cursor);
+                self.text.insert(at, c);
+                self.cursor += 1;
+            }
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                let at = self.byte_index(self.cursor);
+                self.text.remove(at);
+            }
+            KeyCode::Delete if self.cursor < len => {
+                let at = self.byte_index(self.cursor);
+                self.text.remove(at);
+            }
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(len),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = len,
+            _ => {}
+        }
+    }
+}
+
+/// A transient message shown in the status line below the input box.
+struct StatusMessage {
+    text: String,
+    is_error: bool,
+    shown_at: Instant,
+}
+
+impl StatusMessage {
+    fn info(text: impl Into<String>) -> Self {
+        StatusMessage::new(text.into(), false)
+    }
+
+    fn error(text: impl Into<String>) -> Self {
+        StatusMessage::new(text.into(), true)
+    }
+
+    fn new(text: String, is_error: bool) -> Self {
+        StatusMessage {
+            text: format!("{} {}", Local::now().format("%H:%M:%S"), text),
+            is_error,
+            shown_at: Instant::now(),
+        }
+    }
+}
+
+/// Terminals whose fonts usually lack emoji, checked against `TERM` at startup.
+const EMOJI_UNFRIENDLY_TERMS: [&str; 5] = ["linux", "dumb", "vt100", "vt220", "cons25"];
+
+/// ASCII stand-ins for the emoji in messages, used with `ascii`.
+const ASCII_FALLBACKS: [(&str, &str); 4] = [("⚠️", "!"), ("⏰", "!"), ("⏱", "t"), ("✅", "ok")];
+
+fn emoji_unfriendly(term: &str) -> bool {
+    EMOJI_UNFRIENDLY_TERMS.contains(&term)
+}
+
+fn ascii_fallback(text: &str) -> String {
+    ASCII_FALLBACKS
+        .iter()
+        .fold(text.to_string(), |text, (emoji, ascii)| {
+            text.replace(emoji, ascii)
+        })
+}
+
+const DEFAULT_TASKS_FILE: &str = "tasks.md";
+const CONFIG_FILE: &str = "tasksmanager.toml";
+const STATE_FILE: &str = ".tasksmanager-state.json";
+const TRASH_FILE: &str = ".trash.md";
+/// How many recently opened tasks files the state remembers for `startup`.
+const RECENT_FILES_LIMIT: usize = 10;
+/// The choices of the story points picker (`P`), before "other" and "none".
+const POINT_VALUES: [u32; 6] = [1, 2, 3, 5, 8, 13];
+/// Status messages dim after `STATUS_FADE` and disappear after `STATUS_TTL`.
+const STATUS_FADE: Duration = Duration::from_secs(3);
+const STATUS_TTL: Duration = Duration::from_secs(6);
+/// The footer cheatsheet shows this many bindings at a time and moves on to
+/// the next page every `FOOTER_ROTATE`, or when `?` is pressed.
+const FOOTER_PAGE_SIZE: usize = 6;
+const FOOTER_ROTATE: Duration = Duration::from_secs(5);
+/// How often the idle reminder asks git for the time of the last commit.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// Enter presses closer together than this count as one held key.
+const TOGGLE_REPEAT_WINDOW: Duration = Duration::from_millis(200);
+/// Longest wait between redraws while a background test run streams output.
+const STREAM_INTERVAL_MS: u64 = 100;
+/// How long the `celebrate` animation plays, and how long each frame shows.
+const CELEBRATION: Duration = Duration::from_millis(2000);
+const CELEBRATION_FRAME_MS: u64 = 150;
+/// Animation frames as (emoji, ASCII) pairs, each drawn in its own color.
+const CELEBRATION_FRAMES: [(&str, &str, Color); 4] = [
+    ("🎉", "*", Color::Yellow),
+    ("✨", "+", Color::Magenta),
+    ("🎊", "x", Color::Cyan),
+    ("✨", "+", Color::Green),
+];
+/// `x` asks before changing more subtasks than this at once.
+const SUBTASK_CONFIRM_OVER: usize = 5;
+/// Longer test commands are cut to this many characters in the list title.
+const TEST_COMMAND_WIDTH: usize = 40;
+
+/// Bindings of the task list, most used first.
+const VIEW_KEYS: &[(&str, &str)] = &[
+    ("Enter", "toggle status"),
+    ("Space", "toggle status"),
+    ("a", "add"),
+    ("N", "add from template"),
+    ("e", "edit"),
+    ("d", "delete"),
+    ("t", "test+commit"),
+    ("Alt+t", "test+amend last TCR commit"),
+    ("q", "quit"),
+    ("j/k", "move"),
+    ("J/K", "move task down/up"),
+    ("]/[", "next/prev Working"),
+    ("A", "append"),
+    ("S", "split"),
+    ("Tab", "indent"),
+    ("Shift+Tab", "outdent"),
+    ("y", "duplicate"),
+    (".", "repeat last change"),
+    ("Y", "copy to clipboard"),
+    ("*", "flag for review"),
+    ("p", "pin to top"),
+    ("#", "edit tags"),
+    ("m", "move to another file"),
+    ("C", "set current task"),
+    ("h", "hide until restart"),
+    ("U", "unhide all"),
+    ("P", "story points"),
+    ("/", "filter"),
+    ("T", "set test command"),
+    ("B", "batch TCR over Working tasks"),
+    ("v", "list/table view"),
+    ("G", "group by tag"),
+    ("r", "reload from file"),
+    ("s", "sort"),
+    ("R", "reset filter and sort"),
+    ("F", "defer overdue tasks"),
+    ("E", "export"),
+    ("o", "last test output"),
+    ("O", "split view"),
+    ("c", "commit the task list"),
+    ("w", "save now"),
+    ("M", "set status of all in view"),
+    ("x", "check/uncheck all subtasks"),
+    ("I", "import"),
+    ("Ctrl+o", "open URL in task"),
+    ("Ctrl+p", "plain/colors"),
+    ("W", "task test directory"),
+    ("!", "keep a path from revert"),
+    ("Ctrl+t", "task test command"),
+    ("i", "open linked issue"),
+    ("X", "trash / restore deleted"),
+    ("L", "TCR commit log"),
+    ("D", "relative/absolute dates"),
+    ("=", "list density"),
+    ("?", "next hints"),
+    ("%", "statistics"),
+    ("H", "help"),
+];
+/// Bindings shared by every input box.
+const INPUT_KEYS: &[(&str, &str)] = &[
+    ("Enter", "confirm"),
+    ("Esc", "cancel"),
+    ("←/→", "move cursor"),
+    ("Home/End", "start/end"),
+    ("Del", "delete forward"),
+    ("Ctrl+u", "clear"),
+    ("Ctrl+w", "delete word"),
+];
+static URL_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"[a-zA-Z][a-zA-Z0-9+.-]*://[^\s<>()\[\]]+").unwrap());
+static PLACEHOLDER_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{[^{}\s]+\}").unwrap());
+
+/// Column widths of the table view: status, priority, description, due, tags.
+const TABLE_WIDTHS: [Constraint; 5] = [
+    Constraint::Length(11),
+    Constraint::Length(8),
+    Constraint::Percentage(55),
+    Constraint::Length(16),
+    Constraint::Percentage(20),
+];
+
+const DISMISS_KEYS: &[(&str, &str)] = &[("any key", "close")];
+const IMPORT_KEYS: &[(&str, &str)] = &[("m", "merge"), ("r", "replace"), ("Esc", "cancel")];
+const OUTPUT_KEYS: &[(&str, &str)] = &[
+    ("j/k", "scroll"),
+    ("f", "full output / last lines"),
+    ("Esc", "close"),
+];
+const CONFIRM_KEYS: &[(&str, &str)] = &[("y", "confirm"), ("Esc", "cancel")];
+const PICKER_KEYS: &[(&str, &str)] = &[("j/k", "move"), ("Enter", "open"), ("Esc", "quit")];
+const CONFLICT_KEYS: &[(&str, &str)] = &[
+    ("o", "keep ours"),
+    ("t", "keep theirs"),
+    ("b", "keep both"),
+    ("k", "previous conflict"),
+    ("Esc", "quit without writing"),
+];
+const BULK_KEYS: &[(&str, &str)] = &[
+    ("p", "Pending"),
+    ("w", "Working"),
+    ("d", "Done"),
+    ("Esc", "cancel"),
+];
+const TAG_KEYS: &[(&str, &str)] = &[
+    ("j/k", "move"),
+    ("Space", "add/remove"),
+    ("a", "new tag"),
+    ("Esc", "close"),
+];
+const TAG_INPUT_KEYS: &[(&str, &str)] = &[("Tab", "complete"), ("Enter", "add"), ("Esc", "back")];
+const MOVE_KEYS: &[(&str, &str)] = &[("j/k", "move"), ("Enter", "pick"), ("Esc", "cancel")];
+const TRASH_KEYS: &[(&str, &str)] = &[("j/k", "move"), ("Enter", "restore"), ("Esc", "close")];
+const LOG_KEYS: &[(&str, &str)] = &[("j/k", "move"), ("Enter", "diff stat"), ("Esc", "close")];
+const POINTS_KEYS: &[(&str, &str)] = &[("j/k", "move"), ("Enter", "set"), ("Esc", "cancel")];
+const TEMPLATE_KEYS: &[(&str, &str)] = &[("j/k", "move"), ("Enter", "use"), ("Esc", "cancel")];
+const TEMPLATE_INPUT_KEYS: &[(&str, &str)] = &[
+    ("Enter", "add"),
+    ("Tab", "next placeholder"),
+    ("Esc", "cancel"),
+];
+
+/// Command line flags.
+#[derive(Debug, Default)]
+struct Args {
+    /// `--readonly`: look at the tasks without changing or writing anything.
+    readonly: bool,
+    /// `--ascii`: the `ascii` setting for this run.
+    ascii: bool,
+    /// `--plain`: the `plain` setting for this run.
+    plain: bool,
+    /// `--markdown FILE`: work on the checkboxes of another Markdown file.
+    markdown: Option<String>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+        let mut parsed = Args::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--readonly" => parsed.readonly = true,
+                "--ascii" => parsed.ascii = true,
+                "--plain" => parsed.plain = true,
+                "--markdown" => {
+                    parsed.markdown = Some(args.next().ok_or("--markdown needs a file")?);
+                }
+                _ => {
+                    return Err(format!(
+                        "unknown argument: {arg} (expected --readonly, --ascii, --plain or --markdown FILE)"
+                    ));
+                }
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// Exits with 0 when the user quits, 1 when the app fails (after the
+/// terminal is restored) and 2 for bad arguments.
+fn main() -> ExitCode {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("tasksmanager: {e}");
+            return ExitCode::from(2);
+        }
+    };
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("tasksmanager: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Runs the app in the alternate screen. The terminal is restored however
+/// the app ends, before its error is returned.
+fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    // Set instead of killing the process, so the loop can exit through the
+    // teardown below and the terminal is left usable.
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM, SIGHUP] {
+        signal_hook::flag::register(signal, Arc::clone(&shutdown))?;
+    }
+    // Once the terminal hangs up, crossterm's event read can spin on it and
+    // the loop never looks at the flag again: give it a moment to wind down,
+    // then quit for it, saving what it would have, and end the process.
+    let hangup = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(SIGHUP, Arc::clone(&hangup))?;
+    let teardown: Arc<Mutex<Option<Teardown>>> = Arc::default();
+    let pending = Arc::clone(&teardown);
+    std::thread::spawn(move || {
+        while !hangup.load(Ordering::Relaxed) {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        std::thread::sleep(Duration::from_secs(1));
+        // Waits while the loop carries out its own, which leaves `None`, and is
+        // held until the end so the loop cannot start one after this.
+        let mut pending = pending.lock().unwrap();
+        if let Some(teardown) = pending.take() {
+            let _ = teardown.run();
+        }
+        let _ = disable_raw_mode();
+        std::process::exit(129);
+    });
+
+    enable_raw_mode()?;
+    let res = (|| {
+        execute!(stdout(), EnterAlternateScreen, EnableMouseCapture)?;
+        let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+        run_app(&mut terminal, args, &shutdown, &teardown)
+    })();
+
+    // Best effort: a failing step must not keep the others from running.
+    let _ = disable_raw_mode();
+    let _ = execute!(
+        stdout(),
+        LeaveAlternateScreen,
+        DisableMouseCapture,
+        crossterm::cursor::Show
+    );
+    // A warning from the way out would be lost in the alternate screen.
+    if let Some(warning) = res? {
+        eprintln!("tasksmanager: {warning}");
+    }
+    Ok(())
+}
+
+/// Runs the UI until the user quits. What went wrong while quitting that is
+/// not worth failing over, like the last `commit_on_change` commit, is
+/// returned to be printed once the terminal is restored.
+fn run_app(
+    terminal: &mut Terminal<CrosstermBackend<std::io::Stdout>>,
+    args: &Args,
+    shutdown: &AtomicBool,
+    teardown: &Mutex<Option<Teardown>>,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let (mut config, config_error) = match load_config() {
+        Ok(config) => (config, None),
+        Err(e) => {
+            let config = Config {
+                unreadable: true,
+                ..Config::default()
+            };
+            (config, Some(e))
+        }
+    };
+    config.readonly = args.readonly;
+    config.ascii_flag = args.ascii;
+    config.open_file = args.markdown.clone();
+    config.markdown = args.markdown.is_some();
+    let tracker_error = match config.issue_url.clone() {
+        Some(url) => match IssueTracker::new(url, &config.issue_pattern) {
+            Ok(tracker) => {
+                let _ = ISSUE_TRACKER.set(tracker);
+                None
+            }
+            Err(e) => Some(e),
+        },
+        None => None,
+    };
+    let mut state = load_state();
+    if !config.markdown {
+        match config.startup.as_str() {
+            "last" => {
+                config.open_file = state
+                    .recent_files
+                    .first()
+                    .filter(|file| Path::new(file).is_file())
+                    .cloned();
+            }
+            "picker" => {
+                let mut files = state.recent_files.clone();
+                if !files.contains(&config.tasks_file) {
+                    files.push(config.tasks_file.clone());
+                }
+                match pick_file(terminal, &files, shutdown)? {
+                    Some(file) => config.open_file = Some(file),
+                    None => return Ok(None),
+                }
+            }
+            _ => {}
+        }
+    }
+    check_tasks_path(Path::new(config.tasks_file()), !config.readonly)?;
+    // A merge that left conflict markers is settled here, before loading
+    // would refuse the file.
+    let regions = fs::read_to_string(config.tasks_file())
+        .ok()
+        .and_then(|content| conflict_regions(&content))
+        .filter(|_| !config.readonly);
+    let resolved = match regions {
+        Some(regions) => resolve_conflicts(terminal, config.tasks_file(), &regions, shutdown)?,
+        None => None,
+    };
+    let (frontmatter, mut tasks) = read_tasks(&mut config)?;
+    // Only tasks files are offered again; a `--markdown` file would be rewritten as one.
+    if !config.markdown {
+        let file = config.tasks_file().to_string();
+        state.recent_files.retain(|recent| *recent != file);
+        state.recent_files.insert(0, file);
+        state.recent_files.truncate(RECENT_FILES_LIMIT);
+    }
+    let snapshot_key = std::path::absolute(config.tasks_file()).map_or_else(
+        |_| config.tasks_file().to_string(),
+        |p| p.display().to_string(),
+    );
+    let changes = state
+        .snapshots
+        .get(&snapshot_key)
+        .map(|snapshot| changes_since(snapshot, &tasks))
+        .unwrap_or_default();
+    config.frontmatter = frontmatter;
+    let duplicates = if config.dedup_on_load {
+        dedup_tasks(&mut tasks)
+    } else {
+        0
+    };
+    let mut selected = state
+        .last_selected
+        .and_then(|id| tasks.iter().position(|t| t.id == id))
+        .unwrap_or(0);
+    let today = Local::now().date_naive();
+    let due_banner = config.due_banner && tasks.iter().any(|t| t.is_due(today));
+    let new_day = state.last_opened.replace(today).filter(|day| *day < today);
+    // Ids, as the prompt rolls over just the tasks it listed.
+    let rolling: Vec<u64> = if config.daily_rollover && !config.readonly && new_day.is_some() {
+        tasks
+            .iter()
+            .filter(|t| t.rolls_over(today) || t.resets_daily(today))
+            .map(|t| t.id)
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let greeting = new_day
+        .filter(|_| config.daily_rollover)
+        .map(|day| greeting(&tasks, day, today, Local::now().hour()));
+    let after_rollover = if !changes.is_empty() {
+        "changes"
+    } else if due_banner {
+        "banner"
+    } else {
+        "view"
+    };
+    let mut mode = if rolling.is_empty() {
+        after_rollover
+    } else {
+        "rollover"
+    }; // or "input" or "edit" or "test"
+    let mut input = InputBuffer::default();
+    let mut test_command = config
+        .frontmatter
+        .as_ref()
+        .and_then(|frontmatter| frontmatter.get("test_command"))
+        .unwrap_or_else(|| String::from(" "));
+    let mut status: Option<StatusMessage> = resolved.map(|count| {
+        StatusMessage::info(format!(
+            "Resolved {count} conflict{} in {}.",
+            if count == 1 { "" } else { "s" },
+            config.tasks_file()
+        ))
+    });
+    if status.is_none() && rolling.is_empty() {
+        status = greeting.clone().map(StatusMessage::info);
+    }
+    let mut filter_query = config.view.filter.clone();
+    let mut view_filter = Filter::parse(&filter_query);
+    let started = Instant::now();
+    let mut cheat_page = 0;
+    let mut batch_mode = false;
+    let mut table_view = false;
+    let mut plain = config.plain
+        || args.plain
+        || std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    // Row of the cursor in the grouped list (`G`), where headings take rows
+    // too; past the end it goes to the selected task.
+    let mut group_row = usize::MAX;
+    // Tag groups folded down to their heading, `None` for the untagged one.
+    let mut collapsed: HashSet<Option<String>> = HashSet::new();
+    // Kept across frames so the scroll offset only moves when the selection leaves the screen.
+    let mut list_state = ListState::default();
+    let mut table_state = TableState::default();
+    let mut pending_import: Vec<Task> = Vec::new();
+    let mut trash = if config.persist_trash {
+        load_trash()
+    } else {
+        VecDeque::new()
+    };
+    let mut trash_selected = 0;
+    // Tasks files `m` offers to move the selected task to, and the highlighted one.
+    let mut move_files: Vec<String> = Vec::new();
+    let mut move_selected = 0;
+    // Highlighted row of the tag editor (`#`), over `all_tags` of the file.
+    let mut tag_selected = 0;
+    let mut template_selected = 0;
+    let mut points_selected = 0;
+    // The tasks `M` sets, by id, and the status picked for them.
+    let mut bulk_ids: Vec<u64> = Vec::new();
+    let mut bulk_status: Option<Status> = None;
+    // Tasks `h` hid from the view until restart, by id; never saved.
+    let mut hidden: HashSet<u64> = HashSet::new();
+    let mut tcr_commits: Vec<TcrCommit> = Vec::new();
+    let mut log_selected = 0;
+    // `git show --stat` of the commit picked in the log, with its hash.
+    let mut log_stat: Option<(String, Vec<String>)> = None;
+    // Output of the last test run, shown by `o` and after a failing `t`.
+    let mut test_output: Vec<String> = Vec::new();
+    let mut output_scroll: u16 = 0;
+    // Runs started with `t` in the split view go on in the background.
+    let mut split_view = false;
+    let mut test_job: Option<TestJob> = None;
+    // Where `t` runs the tests when `test_pane` is on; `None` runs them inline.
+    let multiplexer = if config.test_pane {
+        Multiplexer::detect()
+    } else {
+        None
+    };
+    // The task `t` commits for wherever the cursor is; batch mode follows the cursor.
+    let mut current_task = state.current_task;
+    // `t` offers to `git init` first when started outside any repository.
+    let mut repo_missing = config.vcs == "git" && repo_root().is_none();
+    let mut finished_run: Option<TestRun> = None;
+    // Id of the task a running TCR cycle will commit as completed.
+    let mut tcr_task: Option<u64> = None;
+    // Paths `!` keeps out of TCR reverts this session, as root-anchored
+    // patterns along with the path as typed.
+    let mut kept_paths: Vec<(String, String)> = Vec::new();
+    // Exit status of the last finished test run, for the commit summary.
+    let mut last_exit: Option<i32> = None;
+    // When the `celebrate` animation started, and what it says.
+    let mut celebration: Option<(Instant, String)> = None;
+    let mut all_done = !tasks.is_empty() && tasks.iter().all(|t| t.status == Status::Done);
+    // Whether a pass of the running cycle amends the last TCR commit (Alt+t).
+    let mut amend_tcr = false;
+    let mut last_toggle: Option<Instant> = None;
+    let mut last_action: Option<Repeat> = None;
+    // Message of a TCR commit that passed its tests, made once any completion note is in.
+    let mut tcr_commit: Option<String> = None;
+    // The task a completion note (`done_note`) is being typed for.
+    let mut note_task: Option<u64> = None;
+    // What the last TCR commit ran and recorded, shown once after it.
+    let mut commit_summary: Vec<String> = Vec::new();
+    let mut stats: Option<Stats> = None;
+    // Opened on first use and kept, as on X11 the copied text lives only as long as it does.
+    let mut clipboard: Option<Clipboard> = None;
+    let mut last_idle_check: Option<Instant> = None;
+    let mut last_reminder: Option<Instant> = None;
+    // When the task list was last saved without being committed, for `commit_on_change`.
+    let mut last_change: Option<Instant> = None;
+    // A tasks or trash file that could not be written, reported in the status
+    // line over whatever the change itself said.
+    let mut write_error: Option<io::Error> = None;
+    if !config.ascii()
+        && let Ok(term) = std::env::var("TERM")
+        && emoji_unfriendly(&term)
+    {
+        status = Some(StatusMessage::info(format!(
+            "TERM={term} may not show emoji; start with --ascii or set ascii = true."
+        )));
+    }
+    if let Some(e) = tracker_error {
+        status = Some(StatusMessage::error(format!("⚠️ {e}")));
+    }
+    if let Some(e) = config_error {
+        // A parse error runs on over several lines with the offending one quoted.
+        let first = e.lines().next().unwrap_or_default();
+        status = Some(StatusMessage::error(format!(
+            "⚠️ {first}. Running with the defaults; it is not saved over."
+        )));
+    }
+    if duplicates > 0 {
+        status = Some(StatusMessage::info(format!(
+            "Removed {duplicates} duplicate task(s) from {}",
+            config.tasks_file()
+        )));
+    }
+    if !in_repo(Path::new(config.tasks_file()))
+        && let Some(root) = repo_root()
+    {
+        status = Some(StatusMessage::error(format!(
+            "⚠️ {} is outside the git repository at {}; commits will not include it",
+            config.tasks_file(),
+            root.display()
+        )));
+    }
+
+    loop {
+        if shutdown.load(Ordering::Relaxed) {
+            break;
+        }
+        let now = Local::now().naive_local();
+        let today = now.date();
+        if let Some(job) = test_job.take_if(|job| job.handle.is_finished()) {
+            finished_run = Some(job.handle.join().unwrap_or_default());
+        }
+        if let Some(run) = finished_run.take() {
+            let verdict = run.verdict(
+                &config.exit_codes,
+                config.fail_on_pattern.as_deref(),
+                config.require_pattern.as_deref(),
+            );
+            last_exit = run.exit_code;
+            test_output = run.output;
+            output_scroll = 0;
+            // The task selected when `t` was pressed, wherever it is now.
+            let target = tcr_task
+                .take()
+                .and_then(|id| tasks.iter().position(|t| t.id == id));
+            if verdict == Verdict::Pass {
+                let completes = config.commit_policy == "done";
+                // Under the "done" policy only finishing a task earns a commit.
+                let target = target.filter(|&i| !completes || tasks[i].status != Status::Done);
+                if (batch_mode || completes)
+                    && let Some(task) = target.and_then(|i| tasks.get_mut(i))
+                {
+                    task.set_status(Status::Done, now);
+                }
+                save_tasks(&tasks, &config).unwrap_or_else(|e| write_error = Some(e));
+                last_change = None;
+                if completes && target.is_none() {
+                    status = Some(StatusMessage::info(
+                        "Tests passed, nothing committed: commit_policy = \"done\" commits only when completing a task.",
+                    ));
+                }
+                if let Some(task) = target.map(|i| &tasks[i]) {
+                    if config.celebrate {
+                        celebration = Some((
+                            Instant::now(),
+                            format!("Tests passed: \"{}\" done!", task.description),
+                        ));
+                    }
+                    tcr_commit = Some(commit_message(
+                        &format!("{TCR_PREFIX} completed task \"{}\"", task.description),
+                        config.commit_subject_length,
+                    ));
+                    if config.done_note {
+                        note_task = Some(task.id);
+                        input.clear();
+                        mode = "done_note";
+                    }
+                }
+                if batch_mode {
+                    match next_working(&tasks, target.unwrap_or(selected)) {
+                        Some(next) => selected = next,
+                        None => {
+                            batch_mode = false;
+                            status = Some(StatusMessage::info(
+                                "Queue complete: no Working tasks left.",
+                            ));
+                        }
+                    }
+                }
+            } else if let Verdict::Ignore(reason) = &verdict {
+                status = Some(StatusMessage::error(format!(
+                    "Tests inconclusive ({reason}, mapped to \"ignore\"): nothing committed or reverted."
+                )));
+                if !split_view && !test_output.is_empty() {
+                    mode = "output";
+                }
+            } else if let Verdict::Fail(reason) = verdict {
+                let ignored = load_tcrignore();
+                let tasks_pattern = root_pattern(Path::new(config.tasks_file()))
+                    .filter(|_| config.revert_keeps_tasks_file);
+                let protected: Vec<String> = ignored
+                    .iter()
+                    .cloned()
+                    .chain(tasks_pattern.clone())
+                    .chain(kept_paths.iter().map(|(pattern, _)| pattern.clone()))
+                    .collect();
+                let mut kept = Vec::new();
+                if !ignored.is_empty() {
+                    kept.push(format!("{} ({TCRIGNORE_FILE})", ignored.join(", ")));
+                }
+                if !kept_paths.is_empty() {
+                    kept.push(format!("{} (kept with !)", kept_list(&kept_paths)));
+                }
+                if tasks_pattern.is_some() {
+                    kept.push(config.tasks_file().to_string());
+                }
+                status = Some(StatusMessage::error(
+                    if let Err(e) = config.vcs().revert(&protected) {
+                        format!("Tests failed ({reason}), and reverting failed: {e}")
+                    } else if kept.is_empty() {
+                        format!("Tests failed ({reason}), changes reverted.")
+                    } else {
+                        format!(
+                            "Tests failed ({reason}), changes reverted except {}",
+                            kept.join(" and ")
+                        )
+                    },
+                ));
+                if !split_view && !test_output.is_empty() {
+                    mode = "output";
+                }
+            }
+        }
+        // A TCR commit waits while its completion note is being typed.
+        if mode != "done_note"
+            && let Some(message) = tcr_commit.take()
+        {
+            let vcs = config.vcs();
+            let (committed, verb) = if std::mem::take(&mut amend_tcr) {
+                (vcs.amend(&[], config.no_verify), "amended")
+            } else {
+                (vcs.commit(&message, &[], config.no_verify), "committed")
+            };
+            status = Some(match committed {
+                Ok(()) => match vcs.last_commit() {
+                    Ok((hash, stat)) => {
+                        let totals = stat.last().map_or("", |line| line.trim());
+                        let info =
+                            StatusMessage::info(format!("Tests passed, {verb} {hash}: {totals}"));
+                        let subject = if verb == "amended" {
+                            "(amended, message kept)"
+                        } else {
+                            message.lines().next().unwrap_or_default()
+                        };
+                        let mut summary = vec![
+                            format!("Test command  {}", test_command.trim()),
+                            format!("Exit status   {} (passed)", last_exit.unwrap_or_default()),
+                            format!("Commit        {hash} {subject}"),
+                            "Files changed".to_string(),
+                        ];
+                        summary.extend(stat);
+                        if split_view {
+                            // The pane keeps the output; the summary goes below it.
+                            test_output.push(String::new());
+                            test_output.extend(summary);
+                        } else if !batch_mode {
+                            commit_summary = summary;
+                            mode = "summary";
+                        }
+                        info
+                    }
+                    Err(_) => StatusMessage::info("Tests passed, changes committed."),
+                },
+                Err(e) => StatusMessage::error(format!("Commit failed: {e}")),
+            });
+        }
+        let now_all_done = !tasks.is_empty() && tasks.iter().all(|t| t.status == Status::Done);
+        if config.celebrate && now_all_done && !all_done {
+            celebration = Some((Instant::now(), "All tasks done!".to_string()));
+        }
+        all_done = now_all_done;
+        if celebration
+            .as_ref()
+            .is_some_and(|(started, _)| started.elapsed() > CELEBRATION)
+        {
+            celebration = None;
+        }
+        // The filter being typed applies as it changes; Esc goes back to the saved one.
+        let query = if mode == "filter" {
+            input.text.trim()
+        } else {
+            filter_query.as_str()
+        };
+        let typed_filter = (mode == "filter").then(|| Filter::parse(query));
+        let filter = typed_filter.as_ref().unwrap_or(&view_filter);
+        let narrowed = !query.is_empty() || !hidden.is_empty();
+        let mut visible: Vec<usize> = (0..tasks.len())
+            .filter(|&i| filter.matches(&tasks[i]) && !hidden.contains(&tasks[i].id))
+            .collect();
+        config.view.sort.apply(&tasks, &mut visible);
+        pin_to_top(&tasks, &mut visible);
+        if !visible.contains(&selected) {
+            selected = visible
+                .iter()
+                .copied()
+                .find(|&i| i > selected)
+                .or(visible.last().copied())
+                .unwrap_or(tasks.len());
+        }
+        let grouped = config.view.group_by_tag && !table_view;
+        // Tab, Shift+Tab, J and K work on file order, so only while it is what shows.
+        let file_order = !grouped && visible.iter().copied().eq(0..tasks.len());
+        let rows = if grouped {
+            group_rows(&tasks, &visible, &collapsed)
+        } else {
+            Vec::new()
+        };
+        if group_row >= rows.len() {
+            group_row = rows
+                .iter()
+                .position(|row| *row == GroupRow::Task(selected))
+                .unwrap_or(0);
+        }
+        // Keys that pick a task by itself (J/K, ], ...) take the cursor to its first row.
+        if let Some(&GroupRow::Task(i)) = rows.get(group_row)
+            && i != selected
+        {
+            match rows.iter().position(|row| *row == GroupRow::Task(selected)) {
+                Some(row) => group_row = row,
+                None => selected = i,
+            }
+        }
+        let on_header = matches!(rows.get(group_row), Some(GroupRow::Header(..)));
+        // What `t` would run now, for the title and the `confirm_test` prompt.
+        let (run_command, run_dir) = task_test(
+            &tasks,
+            tcr_target(&tasks, current_task, selected, batch_mode),
+            &test_command,
+        );
+        if config.commit_on_change
+            && last_change
+                .is_some_and(|at| at.elapsed() >= Duration::from_secs(config.commit_debounce_secs))
+        {
+            last_change = None;
+            if let Err(e) =
+                config
+                    .vcs()
+                    .commit("Update task list", &config.commit_paths(), config.no_verify)
+            {
+                status = Some(StatusMessage::error(format!("Commit failed: {e}")));
+            }
+        }
+        if config.idle_reminder_minutes > 0
+            && last_idle_check.is_none_or(|at| at.elapsed() >= IDLE_CHECK_INTERVAL)
+        {
+            last_idle_check = Some(Instant::now());
+            let threshold = config.idle_reminder_minutes * 60;
+            let idle = last_commit_time().map(|at| Local::now().timestamp() - at);
+            if idle.is_some_and(|secs| secs >= threshold as i64)
+                && last_reminder.is_none_or(|at| at.elapsed().as_secs() >= threshold)
+            {
+                last_reminder = Some(Instant::now());
+                if config.idle_reminder == "bell" {
+                    execute!(terminal.backend_mut(), Print('\x07'))?;
+                }
+                status = Some(StatusMessage::error(format!(
+                    "⏰ {} minutes since the last commit. Time for a TCR cycle (t)?",
+                    idle.unwrap_or_default() / 60
+                )));
+            }
+        }
+        if let Some(e) = write_error.take() {
+            status = Some(StatusMessage::error(format!("⚠️ Could not save {e}")));
+        }
+        if status
+            .as_ref()
+            .is_some_and(|s| s.shown_at.elapsed() > STATUS_TTL)
+        {
+            status = None;
+        }
+
+        terminal.draw(|f| {
+            let mut constraints = vec![
+                Constraint::Min(3),
+                Constraint::Length(3),
+                Constraint::Length(1),
+                Constraint::Length(1),
+            ];
+            if config.show_legend {
+                constraints.push(Constraint::Length(1));
+            }
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .margin(1)
+                .constraints(constraints)
+                .split(f.size());
+            let (list_area, pane_area) = if split_view {
+                let halves = Layout::default()
+                    .direction(Direction::Horizontal)
+                    .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+                    .split(chunks[0]);
+                (halves[0], Some(halves[1]))
+            } else {
+                (chunks[0], None)
+            };
+
+            let mut title = format!(
+                "{} — {}",
+                config.list_title(),
+                test_command_label(&run_command)
+            );
+            if let Some(dir) = &run_dir {
+                title.push_str(&format!(" in {}", dir.display()));
+            }
+            if config.readonly {
+                title.push_str(" [read-only]");
+            }
+            if batch_mode {
+                title.push_str(" [batch TCR]");
+            }
+            if !filter_query.is_empty() {
+                title.push_str(&format!(" [filter: {filter_query}]"));
+            }
+            if config.view.sort != Sort::File {
+                title.push_str(&format!(" [by {}]", config.view.sort.label()));
+            }
+            let selected_style = Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD);
+            // The current task is underlined, under the cursor or not.
+            let row_style = |task: &Task, highlighted: bool| {
+                let style = if highlighted {
+                    selected_style
+                } else {
+                    Style::default()
+                };
+                if current_task == Some(task.id) {
+                    style.add_modifier(Modifier::UNDERLINED)
+                } else {
+                    style
+                }
+            };
+
+            if table_view {
+                let header = Row::new(["Status", "Priority", "Description", "Due", "Tags"])
+                    .style(Style::default().add_modifier(Modifier::BOLD));
+                let rows: Vec<Row> = visible
+                    .iter()
+                    .map(|&i| {
+                        let task = &tasks[i];
+                        let tags: Vec<String> = task.tags().map(|tag| format!("#{tag}")).collect();
+                        let row = Row::new(vec![
+                            Cell::from(Spans::from(vec![
+                                Span::styled(
+                                    task.review_gutter(config.ascii()),
+                                    Style::default().fg(Color::Magenta),
+                                ),
+                                Span::styled(
+                                    config.theme.prefix(&task.status),
+                                    Style::default().fg(config.theme.prefix_color(task)),
+                                ),
+                            ])),
+                            Cell::from(task.priority.map_or("", |p| p.label())),
+                            Cell::from(format!(
+                                "{}{}{}",
+                                INDENT.repeat(task.depth),
+                                task.untagged_description(),
+                                subtask_progress(&tasks, i)
+                                    .map(|(done, total)| format!(" [{done}/{total}]"))
+                                    .unwrap_or_default()
+                            )),
+                            Cell::from(
+                                task.due
+                                    .map(|due| format_due(due, today, config.relative_dates))
+                                    .unwrap_or_default(),
+                            ),
+                            Cell::from(tags.join(" ")),
+                        ]);
+                        row.style(row_style(task, i == selected))
+                    })
+                    .collect();
+                let table = Table::new(rows)
+                    .highlight_symbol(if plain { "> " } else { "" })
+                    .header(header)
+                    .block(Block::default().title(title).borders(Borders::ALL))
+                    .widths(&TABLE_WIDTHS)
+                    .column_spacing(1);
+                table_state.select(visible.iter().position(|&i| i == selected));
+                f.render_stateful_widget(table, list_area, &mut table_state);
+            } else {
+                let task_item = |i: usize, highlighted: bool| {
+                    let task = &tasks[i];
+                    let gutter = Span::styled(
+                        task.review_gutter(config.ascii()),
+                        Style::default().fg(Color::Magenta),
+                    );
+                    let prefix = Span::styled(
+                        config.theme.prefix(&task.status),
+                        Style::default().fg(config.theme.prefix_color(task)),
+                    );
+                    let mut line =
+                        format!(" {}{}", INDENT.repeat(task.depth), task.description);
+                    if let Some((done, total)) = subtask_progress(&tasks, i) {
+                        line.push_str(&format!(" [{done}/{total}]"));
+                    }
+                    if task.started.is_some() {
+                        line.push_str(&format!(
+                            " {} {}",
+                            if config.ascii() { "t" } else { "⏱" },
+                            format_minutes(task.tracked_minutes(now))
+                        ));
+                    }
+                    if let Some(points) = task.points {
+                        line.push_str(&format!(" [{points} pts]"));
+                    }
+                    if let Some(due) = task.due {
+                        line.push_str(&format!(
+                            " ({})",
+                            format_due(due, today, config.relative_dates)
+                        ));
+                    }
+                    let spans = Spans::from(vec![gutter, prefix, Span::raw(line)]);
+                    ListItem::new(spans).style(row_style(task, highlighted))
+                };
+                let mut task_items: Vec<ListItem> = if grouped {
+                    rows.iter()
+                        .enumerate()
+                        .map(|(row, item)| match item {
+                            GroupRow::Header(tag, count) => {
+                                let fold = match (collapsed.contains(tag), config.ascii()) {
+                                    (true, false) => "▸",
+                                    (false, false) => "▾",
+                                    (true, true) => ">",
+                                    (false, true) => "v",
+                                };
+                                let name = tag
+                                    .as_ref()
+                                    .map_or_else(|| "untagged".to_string(), |tag| format!("#{tag}"));
+                                let style = if row == group_row {
+                                    selected_style
+                                } else {
+                                    Style::default()
+                                        .fg(Color::Cyan)
+                                        .add_modifier(Modifier::BOLD)
+                                };
+                                ListItem::new(format!("{fold} {name} ({count})")).style(style)
+                            }
+                            &GroupRow::Task(i) => task_item(i, row == group_row),
+                        })
+                        .collect()
+                } else {
+                    visible
+                        .iter()
+                        .map(|&i| task_item(i, i == selected))
+                        .collect()
+                };
+                // Spacers go between rows only, so row n sits at n * stride
+                // and the selection never lands on one.
+                let stride = usize::from(config.item_spacing) + 1;
+                if stride > 1 {
+                    let items = std::mem::take(&mut task_items);
+                    let count = items.len();
+                    for (n, item) in items.into_iter().enumerate() {
+                        task_items.push(item);
+                        if n + 1 < count {
+                            task_items.extend((1..stride).map(|_| ListItem::new(Spans::default())));
+                        }
+                    }
+                }
+
+                let tasks_list = List::new(task_items)
+                    .highlight_symbol(if plain { "> " } else { "" })
+                    .block(Block::default().title(title).borders(Borders::ALL));
+
+                list_state.select(
+                    if grouped {
+                        (!rows.is_empty()).then_some(group_row)
+                    } else {
+                        visible.iter().position(|&i| i == selected)
+                    }
+                    .map(|row| row * stride),
+                );
+                f.render_stateful_widget(tasks_list, list_area, &mut list_state);
+            }
+            if let Some(area) = pane_area {
+                let running = test_job.as_ref().map(|job| job.output.lock().unwrap());
+                let (title, lines): (&str, &[String]) = match &running {
+                    Some(lines) => ("Test output (running...)", lines),
+                    None if test_output.is_empty() => ("Test output (t runs the tests)", &[]),
+                    None => ("Test output (last run)", &test_output),
+                };
+                let height = usize::from(area.height.saturating_sub(2));
+                let text: Vec<Spans> = lines[lines.len().saturating_sub(height)..]
+                    .iter()
+                    .map(|line| Spans::from(line.as_str()))
+                    .collect();
+                f.render_widget(
+                    Paragraph::new(text).block(Block::default().title(title).borders(Borders::ALL)),
+                    area,
+                );
+            }
+            if config.show_legend {
+                f.render_widget(Paragraph::new(Spans::from(config.theme.legend())), chunks[4]);
+            }
+
+            if is_input_mode(mode) {
+                let title = match mode {
+                    "input" if config.multi_add => "Enter task description (Enter adds it and asks for the next, Esc finishes)".to_string(),
+                    "input" => "Enter task description".to_string(),
+                    "points_input" => "Story points (any whole number)".to_string(),
+                    "done_note" => "Done! What did you do? (kept as a note; Esc skips)".to_string(),
+                    "tag_input" => "New tag (Tab completes from the tags in the file)".to_string(),
+                    "move_path" => "Move the task to file (created if missing)".to_string(),
+                    "template_input" => "Fill in the {placeholders} (Tab jumps to the next one)".to_string(),
+                    "edit" => "Edit task description".to_string(),
+                    "test" => "Enter test command (used by 't')".to_string(),
+                    "task_cwd" => "Directory t runs this task's tests in (empty: where the app runs)".to_string(),
+                    "keep_path" if kept_paths.is_empty() => "Path a failed TCR run does not revert this session".to_string(),
+                    "keep_path" => format!(
+                        "Path a failed TCR run does not revert (kept: {}; Enter on one releases it)",
+                        kept_list(&kept_paths)
+                    ),
+                    "task_test" => "Test command for this task (empty: the global one, set with T)".to_string(),
+                    "filter" => "Filter (e.g. working, today, #tag, * for review, words; empty shows all)".to_string(),
+                    "split" => "Split off a new task (inserted after the selected one)".to_string(),
+                    "export" => "Export filter (e.g. done, #tag; empty exports everything)".to_string(),
+                    "import" => "Import from file (.json export, or Markdown/plain text lines)".to_string(),
+                    "import_confirm" => format!(
+                        "Replacing deletes all {} current task(s) for {} imported. Type yes to confirm",
+                        tasks.len(),
+                        pending_import.len()
+                    ),
+                    "defer" => format!(
+                        "Move {} overdue task(s) forward by (e.g. 1d, 1w); Enter confirms, Esc cancels",
+                        tasks.iter().filter(|t| t.is_overdue(today)).count()
+                    ),
+                    _ => unreachable!(),
+                };
+                let input_widget = Paragraph::new(input.text.as_ref())
+                    .block(Block::default().title(title).borders(Borders::ALL))
+                    .style(Style::default().fg(Color::Green));
+                f.render_widget(input_widget, chunks[1]);
+                // Length counter on the bottom border, in the right corner.
+                let length = input.text.chars().count();
+                let counter = format!(" {length} ");
+                let width = (counter.len() as u16).min(chunks[1].width.saturating_sub(2));
+                let corner = Rect {
+                    x: chunks[1].right().saturating_sub(width + 1),
+                    y: chunks[1].bottom().saturating_sub(1),
+                    width,
+                    height: 1,
+                };
+                let color = if config.input_length_warning > 0 && length > config.input_length_warning {
+                    Color::Red
+                } else {
+                    Color::DarkGray
+                };
+                f.render_widget(
+                    Paragraph::new(counter).style(Style::default().fg(color)),
+                    corner,
+                );
+                f.set_cursor(chunks[1].x + 1 + input.cursor as u16, chunks[1].y + 1);
+            }
+
+            if mode == "commit_confirm" {
+                let prompt = Paragraph::new(format!(
+                    "Stage and commit only {} as \"Update task list\"? y: commit, Esc: cancel",
+                    config.commit_paths().join(", ")
+                ))
+                .block(Block::default().title("Commit now").borders(Borders::ALL))
+                .style(Style::default().fg(Color::Green));
+                f.render_widget(prompt, chunks[1]);
+            }
+
+            if mode == "test_confirm" {
+                let prompt = Paragraph::new(format!(
+                    "Run `{}`{}, then commit if it passes or revert if it fails? y: run, Esc: cancel",
+                    run_command.trim(),
+                    run_dir
+                        .as_ref()
+                        .map_or_else(String::new, |dir| format!(" in {}", dir.display()))
+                ))
+                .block(Block::default().title("Run tests").borders(Borders::ALL))
+                .style(Style::default().fg(Color::Green));
+                f.render_widget(prompt, chunks[1]);
+            }
+
+            if mode == "git_init" {
+                let prompt = Paragraph::new(
+                    "TCR needs a git repository. y: git init here and commit every file as \"Initial commit\", Esc: cancel",
+                )
+                .block(Block::default().title("No git repository").borders(Borders::ALL))
+                .style(Style::default().fg(Color::Green));
+                f.render_widget(prompt, chunks[1]);
+            }
+
+            if mode == "bulk_status" || mode == "bulk_confirm" {
+                let text = match &bulk_status {
+                    Some(target) => {
+                        let changing = bulk_ids
+                            .iter()
+                            .filter(|&&id| tasks.iter().any(|t| t.id == id && &t.status != target))
+                            .count();
+                        format!(
+                            "Set {changing} task(s) to {target:?} ({} already are)? y: apply, Esc: cancel",
+                            bulk_ids.len() - changing
+                        )
+                    }
+                    None => format!(
+                        "Set all {} task(s) in the view to: p: Pending, w: Working, d: Done, Esc: cancel",
+                        bulk_ids.len()
+                    ),
+                };
+                let prompt = Paragraph::new(text)
+                    .block(Block::default().title("Set status").borders(Borders::ALL))
+                    .style(Style::default().fg(Color::Green));
+                f.render_widget(prompt, chunks[1]);
+            }
+
+            if mode == "import_choice" {
+                let prompt = Paragraph::new(format!(
+                    "{} task(s) read from the file. m: merge into the list, r: replace the list, Esc: cancel",
+                    pending_import.len()
+                ))
+                .block(Block::default().title("Import").borders(Borders::ALL))
+                .style(Style::default().fg(Color::Green));
+                f.render_widget(prompt, chunks[1]);
+            }
+
+            if let Some((started, text)) = &celebration {
+                let frame = (started.elapsed().as_millis() / u128::from(CELEBRATION_FRAME_MS))
+                    as usize
+                    % CELEBRATION_FRAMES.len();
+                let (emoji, ascii, color) = CELEBRATION_FRAMES[frame];
+                let sparkle = if config.ascii() { ascii } else { emoji };
+                // Each frame the sparkles step one column further out.
+                let gap = " ".repeat(frame + 1);
+                let line = format!("{sparkle}{gap}{text}{gap}{sparkle}");
+                f.render_widget(
+                    Paragraph::new(line).style(Style::default().fg(color).add_modifier(Modifier::BOLD)),
+                    chunks[2],
+                );
+            } else if let Some(message) = &status {
+                let color = if message.shown_at.elapsed() > STATUS_FADE {
+                    Color::DarkGray
+                } else if message.is_error {
+                    Color::Red
+                } else {
+                    Color::Cyan
+                };
+                let text = if config.ascii() {
+                    ascii_fallback(&message.text)
+                } else {
+                    message.text.clone()
+                };
+                let status_line = Paragraph::new(text).style(Style::default().fg(color));
+                f.render_widget(status_line, chunks[2]);
+            } else if !kept_paths.is_empty() {
+                let line = format!("Kept from TCR reverts: {}", kept_list(&kept_paths));
+                f.render_widget(
+                    Paragraph::new(line).style(Style::default().fg(Color::DarkGray)),
+                    chunks[2],
+                );
+            }
+            if mode == "view"
+                && let Some(action) = &last_action
+            {
+                let hint = format!(". repeats: {}", action.label());
+                let width = (hint.chars().count() as u16).min(chunks[2].width);
+                let area = Rect {
+                    x: chunks[2].x + chunks[2].width - width,
+                    width,
+                    ..chunks[2]
+                };
+                f.render_widget(
+                    Paragraph::new(hint).style(Style::default().fg(Color::DarkGray)),
+                    area,
+                );
+            }
+
+            let keys = mode_keys(mode);
+            let pages = keys.len().div_ceil(FOOTER_PAGE_SIZE);
+            let page =
+                (started.elapsed().as_secs() / FOOTER_ROTATE.as_secs()) as usize + cheat_page;
+            let hints: Vec<String> = keys
+                .iter()
+                .skip(page % pages * FOOTER_PAGE_SIZE)
+                .take(FOOTER_PAGE_SIZE)
+                .map(|(key, action)| {
+                    let action = if mode == "view" {
+                        config.view_action(key, action)
+                    } else {
+                        action
+                    };
+                    format!("{key}: {action}")
+                })
+                .collect();
+            let mut footer = hints.join("  ");
+            if pages > 1 {
+                footer.push_str(&format!(
+                    "  ({}/{pages}, ?: more, H: help)",
+                    page % pages + 1
+                ));
+            }
+            // Point totals take the right end of the footer when tasks have points,
+            // after the count of tasks shown while a filter or `h` narrows the list.
+            let mut totals = points_summary(&tasks);
+            if narrowed {
+                let shown = format!("showing {} of {}", visible.len(), tasks.len());
+                totals = if totals.is_empty() {
+                    shown
+                } else {
+                    format!("{shown}, {totals}")
+                };
+            }
+            let width = (totals.chars().count() as u16).min(chunks[3].width);
+            let footer_parts = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Min(0), Constraint::Length(width)])
+                .split(chunks[3]);
+            f.render_widget(
+                Paragraph::new(footer).style(Style::default().fg(Color::DarkGray)),
+                footer_parts[0],
+            );
+            f.render_widget(
+                Paragraph::new(totals).style(Style::default().fg(Color::Cyan)),
+                footer_parts[1],
+            );
+
+            if mode == "trash" {
+                let items: Vec<ListItem> = trash
+                    .iter()
+                    .enumerate()
+                    .map(|(i, deleted)| {
+                        let task = &deleted.tasks[0];
+                        let subtasks = match deleted.tasks.len() - 1 {
+                            0 => String::new(),
+                            1 => " (+1 subtask)".to_string(),
+                            n => format!(" (+{n} subtasks)"),
+                        };
+                        let item = ListItem::new(format!(
+                            "[{}] {}{subtasks}",
+                            task.status.marker(),
+                            task.description
+                        ));
+                        if i == trash_selected {
+                            item.style(
+                                Style::default()
+                                    .fg(Color::Yellow)
+                                    .add_modifier(Modifier::BOLD),
+                            )
+                        } else {
+                            item
+                        }
+                    })
+                    .collect();
+                let title = if trash.is_empty() {
+                    "Trash is empty (Esc to close)"
+                } else {
+                    "Recently deleted (Enter: restore, Esc: close)"
+                };
+                let area = centered_rect(70, 60, f.size());
+                let trash_list =
+                    List::new(items).block(Block::default().title(title).borders(Borders::ALL));
+                f.render_widget(Clear, area);
+                f.render_widget(trash_list, area);
+            }
+
+            if matches!(mode, "tags" | "tag_input")
+                && let Some(task) = tasks.get(selected)
+            {
+                let items: Vec<ListItem> = all_tags(&tasks)
+                    .iter()
+                    .enumerate()
+                    .map(|(i, tag)| {
+                        let on = task.tags().any(|t| t.eq_ignore_ascii_case(tag));
+                        let item = ListItem::new(format!("[{}] #{tag}", if on { 'x' } else { ' ' }));
+                        if i == tag_selected {
+                            item.style(
+                                Style::default()
+                                    .fg(Color::Yellow)
+                                    .add_modifier(Modifier::BOLD),
+                            )
+                        } else {
+                            item
+                        }
+                    })
+                    .collect();
+                let title = if items.is_empty() {
+                    "No tags yet (a: new tag, Esc: close)".to_string()
+                } else {
+                    format!("Tags of \"{}\"", task.untagged_description())
+                };
+                let area = centered_rect(60, 50, f.size());
+                let tag_list =
+                    List::new(items).block(Block::default().title(title).borders(Borders::ALL));
+                f.render_widget(Clear, area);
+                f.render_widget(tag_list, area);
+            }
+
+            if mode == "move_file" {
+                let items: Vec<ListItem> = move_files
+                    .iter()
+                    .map(String::as_str)
+                    .chain(["Other file..."])
+                    .enumerate()
+                    .map(|(i, name)| {
+                        let item = ListItem::new(name.to_string());
+                        if i == move_selected {
+                            item.style(
+                                Style::default()
+                                    .fg(Color::Yellow)
+                                    .add_modifier(Modifier::BOLD),
+                            )
+                        } else {
+                            item
+                        }
+                    })
+                    .collect();
+                let area = centered_rect(60, 50, f.size());
+                let picker = List::new(items).block(
+                    Block::default()
+                        .title("Move the task (and its subtasks) to")
+                        .borders(Borders::ALL),
+                );
+                f.render_widget(Clear, area);
+                f.render_widget(picker, area);
+            }
+
+            if mode == "summary" {
+                let lines: Vec<Spans> = commit_summary
+                    .iter()
+                    .map(|line| Spans::from(line.as_str()))
+                    .collect();
+                let area = centered_rect(70, 50, f.size());
+                let summary = Paragraph::new(lines)
+                    .block(
+                        Block::default()
+                            .title("TCR commit (any key to close)")
+                            .borders(Borders::ALL),
+                    )
+                    .style(Style::default().fg(Color::Green));
+                f.render_widget(Clear, area);
+                f.render_widget(summary, area);
+            }
+
+            if mode == "tcr_log" {
+                let area = centered_rect(80, 70, f.size());
+                let parts = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
+                    .split(area);
+                let items: Vec<ListItem> = tcr_commits
+                    .iter()
+                    .enumerate()
+                    .map(|(i, commit)| {
+                        let item = ListItem::new(format!(
+                            "{} {}  {}",
+                            commit.hash, commit.date, commit.task
+                        ));
+                        if i == log_selected {
+                            item.style(
+                                Style::default()
+                                    .fg(Color::Yellow)
+                                    .add_modifier(Modifier::BOLD),
+                            )
+                        } else {
+                            item
+                        }
+                    })
+                    .collect();
+                let title = if tcr_commits.is_empty() {
+                    "No TCR commits yet (Esc to close)".to_string()
+                } else {
+                    format!(
+                        "TCR commits, newest first: {} (Enter: diff stat, Esc: close)",
+                        tcr_commits.len()
+                    )
+                };
+                let commit_list =
+                    List::new(items).block(Block::default().title(title).borders(Borders::ALL));
+                let (stat_title, stat_lines) = match &log_stat {
+                    Some((hash, lines)) => (format!("Files changed in {hash}"), lines.clone()),
+                    None => ("Files changed".to_string(), Vec::new()),
+                };
+                let stat = Paragraph::new(
+                    stat_lines
+                        .into_iter()
+                        .map(Spans::from)
+                        .collect::<Vec<_>>(),
+                )
+                .block(Block::default().title(stat_title).borders(Borders::ALL));
+                f.render_widget(Clear, area);
+                f.render_widget(commit_list, parts[0]);
+                f.render_widget(stat, parts[1]);
+            }
+
+            if mode == "points" {
+                let mut choices: Vec<String> =
+                    POINT_VALUES.iter().map(|points| points.to_string()).collect();
+                choices.push("other...".to_string());
+                choices.push("none".to_string());
+                let items: Vec<ListItem> = choices
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, choice)| {
+                        let item = ListItem::new(choice);
+                        if i == points_selected {
+                            item.style(
+                                Style::default()
+                                    .fg(Color::Yellow)
+                                    .add_modifier(Modifier::BOLD),
+                            )
+                        } else {
+                            item
+                        }
+                    })
+                    .collect();
+                let area = centered_rect(30, 50, f.size());
+                let picker = List::new(items)
+                    .block(Block::default().title("Story points").borders(Borders::ALL));
+                f.render_widget(Clear, area);
+                f.render_widget(picker, area);
+            }
+
+            if mode == "templates" {
+                let items: Vec<ListItem> = config
+                    .templates
+                    .iter()
+                    .enumerate()
+                    .map(|(i, template)| {
+                        let item = ListItem::new(format!("{}: {}", template.name, template.line()));
+                        if i == template_selected {
+                            item.style(
+                                Style::default()
+                                    .fg(Color::Yellow)
+                                    .add_modifier(Modifier::BOLD),
+                            )
+                        } else {
+                            item
+                        }
+                    })
+                    .collect();
+                let area = centered_rect(70, 60, f.size());
+                let template_list = List::new(items).block(
+                    Block::default()
+                        .title("Templates (Enter: use, Esc: cancel)")
+                        .borders(Borders::ALL),
+                );
+                f.render_widget(Clear, area);
+                f.render_widget(template_list, area);
+            }
+
+            if mode == "output" {
+                let shown = if config.full_output {
+                    0
+                } else {
+                    test_output.len().saturating_sub(config.output_lines)
+                };
+                let title = if shown == 0 {
+                    format!("Test output ({} lines, f: last {})", test_output.len(), config.output_lines)
+                } else {
+                    format!(
+                        "Test output (last {} of {} lines, f: full)",
+                        test_output.len() - shown,
+                        test_output.len()
+                    )
+                };
+                let text: Vec<Spans> = test_output[shown..]
+                    .iter()
+                    .map(|line| Spans::from(line.as_str()))
+                    .collect();
+                let area = centered_rect(90, 80, f.size());
+                let output = Paragraph::new(text)
+                    .block(Block::default().title(title).borders(Borders::ALL))
+                    .scroll((output_scroll, 0));
+                f.render_widget(Clear, area);
+                f.render_widget(output, area);
+            }
+
+            if mode == "help" {
+                let mut lines = vec![Spans::from("Task list"), Spans::from("")];
+                for (key, action) in VIEW_KEYS {
+                    let action = config.view_action(key, action);
+                    lines.push(Spans::from(format!("  {key:<10} {action}")));
+                }
+                lines.push(Spans::from(""));
+                lines.push(Spans::from("Input boxes"));
+                lines.push(Spans::from(""));
+                for (key, action) in INPUT_KEYS {
+                    lines.push(Spans::from(format!("  {key:<10} {action}")));
+                }
+                lines.push(Spans::from(""));
+                lines.push(Spans::from("Statuses"));
+                lines.push(Spans::from(""));
+                for status in Status::ALL {
+                    lines.push(Spans::from(vec![
+                        Span::raw("  "),
+                        Span::styled(
+                            format!("{:<10}", config.theme.prefix(&status)),
+                            Style::default().fg(status.color()),
+                        ),
+                        Span::raw(format!(
+                            " {} (Enter: {})",
+                            status.meaning(),
+                            config.theme.prefix(&status.next())
+                        )),
+                    ]));
+                }
+                let area = centered_rect(90, 90, f.size());
+                let block = Block::default()
+                    .title("Help (any key to close)")
+                    .borders(Borders::ALL);
+                let inner = block.inner(area);
+                f.render_widget(Clear, area);
+                f.render_widget(block, area);
+                // Flow the lines into as many columns as the height requires.
+                let rows = usize::from(inner.height.max(1));
+                let columns = lines.len().div_ceil(rows).max(1);
+                let constraints = vec![Constraint::Ratio(1, columns as u32); columns];
+                let areas = Layout::default()
+                    .direction(Direction::Horizontal)
+                    .constraints(constraints)
+                    .split(inner);
+                for (column, area) in lines.chunks(rows).zip(areas) {
+                    f.render_widget(Paragraph::new(column.to_vec()), area);
+                }
+            }
+
+            if mode == "stats"
+                && let Some(stats) = &stats
+            {
+                let mut lines: Vec<Spans> = stats
+                    .by_status
+                    .iter()
+                    .map(|(status, count)| {
+                        Spans::from(vec![
+                            Span::styled(
+                                format!("{:<10}", format!("{status:?}")),
+                                Style::default().fg(status.color()),
+                            ),
+                            Span::raw(format!(" {count}")),
+                        ])
+                    })
+                    .collect();
+                lines.push(Spans::from(""));
+                lines.push(Spans::from(match stats.average_working_days {
+                    Some(days) => format!("Average time in Working: {days:.1} day(s)"),
+                    None => "Average time in Working: no finished tasks with dates yet".to_string(),
+                }));
+                lines.push(Spans::from(match stats.tcr_commits {
+                    Some(count) => format!("TCR cycles committed: {count}"),
+                    None => "TCR cycles committed: no git history".to_string(),
+                }));
+                let week: Vec<String> = (0..7)
+                    .rev()
+                    .zip(&stats.done_per_day)
+                    .map(|(ago, count)| {
+                        let day = today - chrono::Duration::days(ago);
+                        format!("{} {count}", day.format("%a"))
+                    })
+                    .collect();
+
+                let area = centered_rect(60, 60, f.size());
+                let block = Block::default()
+                    .title("Statistics (any key to close)")
+                    .borders(Borders::ALL);
+                let inner = block.inner(area);
+                f.render_widget(Clear, area);
+                f.render_widget(block, area);
+                let parts = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([
+                        Constraint::Length(lines.len() as u16 + 1),
+                        Constraint::Length(1),
+                        Constraint::Min(1),
+                        Constraint::Length(1),
+                    ])
+                    .split(inner);
+                f.render_widget(Paragraph::new(lines), parts[0]);
+                f.render_widget(Paragraph::new("Done per day, last 7 days:"), parts[1]);
+                f.render_widget(
+                    Sparkline::default()
+                        .data(&stats.done_per_day)
+                        .style(Style::default().fg(Color::Green)),
+                    parts[2],
+                );
+                f.render_widget(Paragraph::new(week.join("  ")), parts[3]);
+            }
+
+            if mode == "detail"
+                && let Some(task) = tasks.get(selected)
+            {
+                let date = |day: Option<NaiveDate>| {
+                    day.map_or("-".to_string(), |day| day.format("%Y-%m-%d").to_string())
+                };
+                let tags: Vec<String> = task.tags().map(|tag| format!("#{tag}")).collect();
+                let mut rows = vec![
+                    ("Description", task.untagged_description()),
+                    ("Status", format!("{:?}", task.status)),
+                    (
+                        "Priority",
+                        task.priority.map_or("-", |p| p.label()).to_string(),
+                    ),
+                    (
+                        "Points",
+                        task.points.map_or("-".to_string(), |p| p.to_string()),
+                    ),
+                    ("Due", date(task.due)),
+                    ("Tags", tags.join(" ")),
+                    ("Worked", date(task.worked)),
+                    ("Done", date(task.done)),
+                    ("Time", format_minutes(task.tracked_minutes(now))),
+                    ("Review", if task.review { "yes" } else { "no" }.to_string()),
+                    ("Pinned", if task.pinned { "yes" } else { "no" }.to_string()),
+                    ("Issue", task.issue_link().unwrap_or_else(|| "-".to_string())),
+                    ("Id", task.id.to_string()),
+                ];
+                for (i, note) in task.notes.iter().enumerate() {
+                    rows.push((if i == 0 { "Notes" } else { "" }, note.clone()));
+                }
+                let lines: Vec<Spans> = rows
+                    .iter()
+                    .map(|(label, value)| {
+                        Spans::from(vec![
+                            Span::styled(
+                                format!("{label:<12}"),
+                                Style::default().add_modifier(Modifier::BOLD),
+                            ),
+                            Span::raw(value.as_str()),
+                        ])
+                    })
+                    .collect();
+                let area = centered_rect(70, 50, f.size());
+                let detail = Paragraph::new(lines)
+                    .block(
+                        Block::default()
+                            .title("Task (any key to close)")
+                            .borders(Borders::ALL),
+                    )
+                    .wrap(Wrap { trim: false });
+                f.render_widget(Clear, area);
+                f.render_widget(detail, area);
+            }
+
+            if mode == "changes" {
+                let mut lines = vec![
+                    Spans::from(format!(
+                        "{} change(s) to {} since you last quit:",
+                        changes.len(),
+                        config.tasks_file()
+                    )),
+                    Spans::from(""),
+                ];
+                lines.extend(changes.iter().take(10).map(|c| Spans::from(c.as_str())));
+                if changes.len() > 10 {
+                    lines.push(Spans::from(format!("...and {} more", changes.len() - 10)));
+                }
+                lines.push(Spans::from(""));
+                lines.push(Spans::from("Press any key to dismiss"));
+                let area = centered_rect(70, 50, f.size());
+                let popup = Paragraph::new(lines)
+                    .block(
+                        Block::default()
+                            .title("Changed since last session")
+                            .borders(Borders::ALL),
+                    )
+                    .wrap(Wrap { trim: false });
+                f.render_widget(Clear, area);
+                f.render_widget(popup, area);
+            }
+
+            if mode == "rollover" {
+                let mut lines = vec![
+                    Spans::from(greeting.clone().unwrap_or_default()),
+                    Spans::from(""),
+                    Spans::from(format!("Roll {} task(s) over to today?", rolling.len())),
+                    Spans::from(""),
+                ];
+                let listed = tasks.iter().filter(|t| rolling.contains(&t.id));
+                for task in listed.take(10) {
+                    let change = if task.rolls_over(today) {
+                        "Working, carried over"
+                    } else {
+                        "#daily, back to Pending"
+                    };
+                    lines.push(Spans::from(format!("- {} ({change})", task.description)));
+                }
+                if rolling.len() > 10 {
+                    lines.push(Spans::from(format!("...and {} more", rolling.len() - 10)));
+                }
+                lines.push(Spans::from(""));
+                lines.push(Spans::from("y: roll over, Esc: leave them as they are"));
+                let area = centered_rect(70, 50, f.size());
+                let popup = Paragraph::new(lines)
+                    .block(Block::default().title("New day").borders(Borders::ALL))
+                    .wrap(Wrap { trim: false });
+                f.render_widget(Clear, area);
+                f.render_widget(popup, area);
+            }
+
+            if mode == "banner" {
+                let due: Vec<&Task> = tasks.iter().filter(|t| t.is_due(today)).collect();
+                let mut lines = vec![
+                    Spans::from(format!("{} task(s) due today or overdue:", due.len())),
+                    Spans::from(""),
+                ];
+                for task in due.iter().take(5) {
+                    if let Some(date) = task.due {
+                        let due = format_due(date, today, config.relative_dates);
+                        lines.push(Spans::from(format!("- {} ({due})", task.description)));
+                    }
+                }
+                if due.len() > 5 {
+                    lines.push(Spans::from(format!("...and {} more", due.len() - 5)));
+                }
+                lines.push(Spans::from(""));
+                lines.push(Spans::from("Press any key to dismiss"));
+                let area = centered_rect(60, 40, f.size());
+                let banner = Paragraph::new(lines)
+                    .block(Block::default().title("Due soon").borders(Borders::ALL))
+                    .style(Style::default().fg(Color::Red));
+                f.render_widget(Clear, area);
+                f.render_widget(banner, area);
+            }
+            if plain {
+                f.render_widget(Monochrome, f.size());
+            }
+        })?;
+
+        // Keys are handled as soon as they arrive; the interval only sets how
+        // often the screen catches up with timers and background work.
+        let interval = if test_job.is_some() {
+            config.poll_interval_ms.min(STREAM_INTERVAL_MS)
+        } else if celebration.is_some() {
+            config.poll_interval_ms.min(CELEBRATION_FRAME_MS)
+        } else {
+            config.poll_interval_ms
+        };
+        // A hangup can leave the poll below spinning for good; the watchdog
+        // in `run` then quits with this.
+        state.record(&snapshot_key, &tasks, selected, current_task);
+        *teardown.lock().unwrap() = Some(Teardown::new(&config, &state, last_change.is_some()));
+        if event::poll(Duration::from_millis(interval.max(1)))?
+            && let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+        {
+            // Raw mode turns Ctrl+C into a key press; treat it like the SIGINT it would be.
+            if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                break;
+            }
+            if key.code == KeyCode::Char('p') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                plain = !plain;
+                status = Some(StatusMessage::info(if plain {
+                    "Plain: no colors, > marks the selection (Ctrl+p for colors)."
+                } else {
+                    "Colors on."
+                }));
+                continue;
+            }
+            // Readline's Ctrl+u and Ctrl+w, in every input box.
+            if is_input_mode(mode)
+                && key.modifiers.contains(KeyModifiers::CONTROL)
+                && let KeyCode::Char(c @ ('u' | 'w')) = key.code
+            {
+                if c == 'u' {
+                    input.clear();
+                } else {
+                    input.delete_word();
+                }
+                continue;
+            }
+            // `.` goes through the arm of the last repeatable change.
+            let repeating = key.code == KeyCode::Char('.') && last_action.is_some();
+            // `y` at the `confirm_test` prompt goes on through the `t` arm.
+            let confirmed = mode == "test_confirm" && key.code == KeyCode::Char('y');
+            if confirmed {
+                mode = "view";
+            }
+            let code = match &last_action {
+                Some(action) if repeating => action.key(),
+                _ if confirmed => KeyCode::Char('t'),
+                _ => key.code,
+            };
+            if mode == "view" && on_header && !header_key(code) {
+                status = Some(StatusMessage::info(
+                    "That key needs a task: move down into the group (Enter folds it).",
+                ));
+                continue;
+            }
+            // Alt+t runs the tests like `t`, but a pass amends the last TCR
+            // commit, so a closely related follow-up does not get its own.
+            // `y` at the `confirm_test` prompt keeps what the first key chose.
+            if mode == "view"
+                && code == KeyCode::Char('t')
+                && !confirmed
+                && test_job.is_none()
+                && !key.modifiers.contains(KeyModifiers::CONTROL)
+            {
+                amend_tcr = key.modifiers.contains(KeyModifiers::ALT);
+                let refusal = if !amend_tcr || repo_missing || config.readonly {
+                    None
+                } else if config.vcs != "git" {
+                    Some("amending needs vcs = \"git\"".to_string())
+                } else {
+                    amend_refusal()
+                };
+                if let Some(reason) = refusal {
+                    amend_tcr = false;
+                    status = Some(StatusMessage::error(format!("Cannot amend: {reason}.")));
+                    continue;
+                }
+            }
+            if config.readonly && mode == "view" && changes_tasks(code, &config) {
+                status = Some(StatusMessage::error(
+                    "Read-only: started with --readonly, so nothing is changed, committed or written.",
+                ));
+                continue;
+            }
+            if mode == "view" && !file_order && rearranges(code) {
+                status = Some(StatusMessage::error(
+                    "Tasks are not shown in file order: R resets the filter, sort and grouping, and pinned tasks (p) stay on top.",
+                ));
+                continue;
+            }
+            match mode {
+                "banner" | "help" | "stats" | "detail" | "summary" => mode = "view",
+                "changes" => mode = if due_banner { "banner" } else { "view" },
+                "rollover" => match key.code {
+                    KeyCode::Char('y') => {
+                        for task in tasks.iter_mut().filter(|t| rolling.contains(&t.id)) {
+                            task.roll_over(now);
+                        }
+                        save_tasks(&tasks, &config).unwrap_or_else(|e| write_error = Some(e));
+                        last_change = Some(Instant::now());
+                        status = Some(StatusMessage::info(format!(
+                            "Rolled {} task(s) over to today, saved.",
+                            rolling.len()
+                        )));
+                        mode = after_rollover;
+                    }
+                    KeyCode::Esc | KeyCode::Char('n') => mode = after_rollover,
+                    _ => {}
+                },
+                "import" => match key.code {
+                    KeyCode::Enter => match import_tasks(input.take().trim()) {
+                        Ok(imported) if imported.is_empty() => {
+                            status = Some(StatusMessage::error("No tasks found in that file."));
+                            mode = "view";
+                        }
+                        Ok(imported) => {
+                            pending_import = imported;
+                            mode = "import_choice";
+                        }
+                        Err(e) => {
+                            status = Some(StatusMessage::error(e));
+                            mode = "view";
+                        }
+                    },
+                    KeyCode::Esc => mode = "view",
+                    code => input.handle_key(code),
+                },
+                "commit_confirm" => match key.code {
+                    KeyCode::Char('y') => {
+                        let paths = config.commit_paths();
+                        status = Some(
+                            match config
+                                .vcs()
+                                .commit("Update task list", &paths, config.no_verify)
+                            {
+                                Ok(()) => {
+                                    last_change = None;
+                                    StatusMessage::info(format!("Committed {}.", paths.join(", ")))
+                                }
+                                Err(e) => StatusMessage::error(format!("Commit failed: {e}")),
+                            },
+                        );
+                        mode = "view";
+                    }
+                    KeyCode::Esc | KeyCode::Char('n') => mode = "view",
+                    _ => {}
+                },
+                "test_confirm" => {
+                    if matches!(key.code, KeyCode::Esc | KeyCode::Char('n')) {
+                        mode = "view";
+                    }
+                }
+                "git_init" => match key.code {
+                    KeyCode::Char('y') => {
+                        status = Some(match init_repo(&[STATE_FILE, TRASH_FILE]) {
+                            Ok(()) => {
+                                repo_missing = false;
+                                StatusMessage::info(
+                                    "Created a git repository with an initial commit; t runs the tests now.",
+                                )
+                            }
+                            Err(e) => StatusMessage::error(e),
+                        });
+                        mode = "view";
+                    }
+                    KeyCode::Esc | KeyCode::Char('n') => mode = "view",
+                    _ => {}
+                },
+                "bulk_status" => {
+                    bulk_status = match key.code {
+                        KeyCode::Char('p') => Some(Status::Pending),
+                        KeyCode::Char('w') => Some(Status::Working),
+                        KeyCode::Char('d') => Some(Status::Done),
+                        _ => None,
+                    };
+                    mode = if bulk_status.is_some() {
+                        "bulk_confirm"
+                    } else if key.code == KeyCode::Esc {
+                        "view"
+                    } else {
+                        "bulk_status"
+                    };
+                }
+                "bulk_confirm" => match key.code {
+                    KeyCode::Char('y') => {
+                        if let Some(target) = bulk_status.take() {
+                            let changed = set_statuses(&mut tasks, &bulk_ids, &target, now);
+                            if changed > 0 {
+                                save_tasks(&tasks, &config)
+                                    .unwrap_or_else(|e| write_error = Some(e));
+                                last_change = Some(Instant::now());
+                            }
+                            status = Some(StatusMessage::info(format!(
+                                "Set {changed} task(s) to {target:?}, saved."
+                            )));
+                        }
+                        mode = "view";
+                    }
+                    KeyCode::Esc | KeyCode::Char('n') => mode = "view",
+                    _ => {}
+                },
+                "import_choice" => match key.code {
+                    KeyCode::Char('m') => {
+                        let count = pending_import.len();
+                        tasks.append(&mut pending_import);
+                        assign_ids(&mut tasks);
+                        save_tasks(&tasks, &config).unwrap_or_else(|e| write_error = Some(e));
+                        last_change = Some(Instant::now());
+                        status = Some(StatusMessage::info(format!(
+                            "Imported {count} task(s), saved."
+                        )));
+                        mode = "view";
+                    }
+                    KeyCode::Char('r') if tasks.is_empty() => {
+                        tasks = std::mem::take(&mut pending_import);
+                        assign_ids(&mut tasks);
+                        save_tasks(&tasks, &config).unwrap_or_else(|e| write_error = Some(e));
+                        last_change = Some(Instant::now());
+                        status = Some(StatusMessage::info(format!(
+                            "Imported {} task(s), saved.",
+                            tasks.len()
+                        )));
+                        mode = "view";
+                    }
+                    KeyCode::Char('r') => {
+                        input.clear();
+                        mode = "import_confirm";
+                    }
+                    KeyCode::Esc => {
+                        pending_import.clear();
+                        mode = "view";
+                    }
+                    _ => {}
+                },
+                "import_confirm" => match key.code {
+                    KeyCode::Enter if input.take().trim().eq_ignore_ascii_case("yes") => {
+                        let backup = format!("{}.bak", config.tasks_file());
+                        let backed_up = if config.backup_on_import {
+                            fs::copy(config.tasks_file(), &backup)
+                                .map(|_| ())
+                                .map_err(|e| e.to_string())
+                        } else {
+                            Ok(())
+                        };
+                        match backed_up {
+                            Ok(()) => {
+                                let lost = tasks.len();
+                                tasks = std::mem::take(&mut pending_import);
+                                assign_ids(&mut tasks);
+                                selected = 0;
+                                save_tasks(&tasks, &config)
+                                    .unwrap_or_else(|e| write_error = Some(e));
+                                last_change = Some(Instant::now());
+                                let mut message = format!(
+                                    "Replaced {lost} task(s) with {} imported, saved.",
+                                    tasks.len()
+                                );
+                                if config.backup_on_import {
+                                    message.push_str(&format!(" Backup in {backup}."));
+                                }
+                                status = Some(StatusMessage::info(message));
+                            }
+                            Err(e) => {
+                                pending_import.clear();
+                                status = Some(StatusMessage::error(format!(
+                                    "Import cancelled, could not back up to {backup}: {e}"
+                                )));
+                            }
+                        }
+                        mode = "view";
+                    }
+                    KeyCode::Enter | KeyCode::Esc => {
+                        pending_import.clear();
+                        status = Some(StatusMessage::info("Import cancelled."));
+                        mode = "view";
+                    }
+                    code => input.handle_key(code),
+                },
+                "points" => match key.code {
+                    KeyCode::Char('j') | KeyCode::Down
+                        if points_selected < POINT_VALUES.len() + 1 =>
+                    {
+                        points_selected += 1;
+                    }
+                    KeyCode::Char('k') | KeyCode::Up => {
+                        points_selected = points_selected.saturating_sub(1);
+                    }
+                    KeyCode::Enter if points_selected == POINT_VALUES.len() => {
+                        let current = tasks.get(selected).and_then(|t| t.points);
+                        input.set(current.map_or(String::new(), |p| p.to_string()), true);
+                        mode = "points_input";
+                    }
+                    KeyCode::Enter => {
+                        if let Some(task) = tasks.get_mut(selected) {
+                            task.points = POINT_VALUES.get(points_selected).copied();
+                            save_tasks(&tasks, &config).unwrap_or_else(|e| write_error = Some(e));
+                            last_change = Some(Instant::now());
+                            status = Some(StatusMessage::info("Story points set, saved."));
+                        }
+                        mode = "view";
+                    }
+                    KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('P') => mode = "view",
+                    _ => {}
+                },
+                "done_note" => match key.code {
+                    KeyCode::Enter => {
+                        let note = input.take().trim().to_string();
+                        if let Some(task) = note_task
+                            .take()
+                            .and_then(|id| tasks.iter_mut().find(|t| t.id == id))
+                            && !note.is_empty()
+                        {
+                            task.notes.push(note.clone());
+                            save_tasks(&tasks, &config).unwrap_or_else(|e| write_error = Some(e));
+                            // A pending TCR commit takes the note along.
+                            match &mut tcr_commit {
+                                Some(message) => message.push_str(&format!("\n\n{note}")),
+                                None => last_change = Some(Instant::now()),
+                            }
+                            status = Some(StatusMessage::info("Note added, saved."));
+                        }
+                        mode = "view";
+                    }
+                    KeyCode::Esc => {
+                        input.clear();
+                        note_task = None;
+                        mode = "view";
+                    }
+                    code => input.handle_key(code),
+                },
+                "points_input" => match key.code {
+                    KeyCode::Enter => match input.text.trim().parse::<u32>() {
+                        Ok(points) => {
+                            input.clear();
+                            if let Some(task) = tasks.get_mut(selected) {
+                                task.points = Some(points);
+                                save_tasks(&tasks, &config)
+                                    .unwrap_or_else(|e| write_error = Some(e));
+                                last_change = Some(Instant::now());
+                                status = Some(StatusMessage::info("Story points set, saved."));
+                            }
+                            mode = "view";
+                        }
+                        Err(_) => {
+                            status = Some(StatusMessage::error(
+                                "Story points are a whole number, e.g. 4 or 20.",
+                            ));
+                        }
+                    },
+                    KeyCode::Esc => mode = "view",
+                    code => input.handle_key(code),
+                },
+                "templates" => match key.code {
+                    KeyCode::Char('j') | KeyCode::Down
+                        if template_selected + 1 < config.templates.len() =>
+                    {
+                        template_selected += 1;
+                    }
+                    KeyCode::Char('k') | KeyCode::Up => {
+                        template_selected = template_selected.saturating_sub(1);
+                    }
+                    KeyCode::Enter => {
+                        if let Some(template) = config.templates.get(template_selected) {
+                            let line = template.line();
+                            match next_placeholder(&line, 0) {
+                                Some(placeholder) => {
+                                    input.set(line, false);
+                                    input.cursor = placeholder.start;
+                                    mode = "template_input";
+                                }
+                                None => {
+                                    input.set(line, true);
+                                    mode = "input";
+                                }
+                            }
+                        }
+                    }
+                    KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('N') => mode = "view",
+                    _ => {}
+                },
+                "tcr_log" => match key.code {
+                    KeyCode::Char('j') | KeyCode::Down if log_selected + 1 < tcr_commits.len() => {
+                        log_selected += 1;
+                    }
+                    KeyCode::Char('k') | KeyCode::Up => {
+                        log_selected = log_selected.saturating_sub(1);
+                    }
+                    KeyCode::Enter => {
+                        if let Some(commit) = tcr_commits.get(log_selected) {
+                            match diff_stat(&commit.hash) {
+                                Ok(lines) => log_stat = Some((commit.hash.clone(), lines)),
+                                Err(e) => status = Some(StatusMessage::error(format!("⚠️ {e}"))),
+                            }
+                        }
+                    }
+                    KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('L') => mode = "view",
+                    _ => {}
+                },
+                "trash" => match key.code {
+                    KeyCode::Char('j') | KeyCode::Down if trash_selected + 1 < trash.len() => {
+                        trash_selected += 1;
+                    }
+                    KeyCode::Char('k') | KeyCode::Up => {
+                        trash_selected = trash_selected.saturating_sub(1);
+                    }
+                    KeyCode::Enter => {
+                        if let Some(deleted) = trash.remove(trash_selected) {
+                            selected = restore_deleted(&mut tasks, deleted);
+                            save_tasks(&tasks, &config).unwrap_or_else(|e| write_error = Some(e));
+                            last_change = Some(Instant::now());
+                            if config.persist_trash {
+                                save_trash(&trash).unwrap_or_else(|e| write_error = Some(e));
+                            }
+                            status = Some(StatusMessage::info("Task restored, saved."));
+                            mode = "view";
+                        }
+                    }
+                    KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('X') => mode = "view",
+                    _ => {}
+                },
+                "move_file" | "move_path" => {
+                    let target = match (mode, key.code) {
+                        ("move_file", KeyCode::Char('j') | KeyCode::Down) => {
+                            move_selected = (move_selected + 1).min(move_files.len());
+                            None
+                        }
+                        ("move_file", KeyCode::Char('k') | KeyCode::Up) => {
+                            move_selected = move_selected.saturating_sub(1);
+                            None
+                        }
+                        ("move_file", KeyCode::Enter) => match move_files.get(move_selected) {
+                            Some(path) => Some(path.clone()),
+                            None => {
+                                input.clear();
+                                mode = "move_path";
+                                None
+                            }
+                        },
+                        ("move_path", KeyCode::Enter) => {
+                            Some(input.take().trim().to_string()).filter(|path| !path.is_empty())
+                        }
+                        (_, KeyCode::Esc) => {
+                            mode = "view";
+                            None
+                        }
+                        ("move_path", code) => {
+                            input.handle_key(code);
+                            None
+                        }
+                        _ => None,
+                    };
+                    if let Some(path) = target
+                        && selected < tasks.len()
+                    {
+                        status = Some(match move_to_file(&mut tasks, selected, &path, &config) {
+                            Ok(count) => {
+                                save_tasks(&tasks, &config)
+                                    .unwrap_or_else(|e| write_error = Some(e));
+                                last_change = Some(Instant::now());
+                                StatusMessage::info(format!(
+                                    "Moved {count} task(s) to {path}, saved."
+                                ))
+                            }
+                            Err(e) => StatusMessage::error(e),
+                        });
+                        mode = "view";
+                    }
+                }
+                "tags" => {
+                    let tags = all_tags(&tasks);
+                    // Removing the last use of a tag takes it off the list.
+                    tag_selected = tag_selected.min(tags.len().saturating_sub(1));
+                    match key.code {
+                        KeyCode::Char('j') | KeyCode::Down if tag_selected + 1 < tags.len() => {
+                            tag_selected += 1;
+                        }
+                        KeyCode::Char('k') | KeyCode::Up => {
+                            tag_selected = tag_selected.saturating_sub(1);
+                        }
+                        KeyCode::Char(' ') | KeyCode::Enter => {
+                            if let (Some(task), Some(tag)) =
+                                (tasks.get_mut(selected), tags.get(tag_selected))
+                            {
+                                let message = if task.tags().any(|t| t.eq_ignore_ascii_case(tag)) {
+                                    task.remove_tag(tag);
+                                    format!("Removed #{tag}, saved.")
+                                } else {
+                                    task.add_tag(tag);
+                                    format!("Added #{tag}, saved.")
+                                };
+                                status = Some(StatusMessage::info(message));
+                                save_tasks(&tasks, &config)
+                                    .unwrap_or_else(|e| write_error = Some(e));
+                                last_change = Some(Instant::now());
+                            }
+                        }
+                        KeyCode::Char('a') => {
+                            input.clear();
+                            mode = "tag_input";
+                        }
+                        KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('#') => mode = "view",
+                        _ => {}
+                    }
+                }
+                "tag_input" => match key.code {
+                    KeyCode::Tab => {
+                        let typed = input.text.trim().trim_start_matches('#').to_lowercase();
+                        if let Some(tag) = all_tags(&tasks)
+                            .into_iter()
+                            .find(|tag| tag.to_lowercase().starts_with(&typed))
+                        {
+                            input.set(tag, true);
+                        }
+                    }
+                    KeyCode::Enter => {
+                        let text = input.take();
+                        let tag = text.trim().trim_start_matches('#');
+                        if tag.is_empty() || tag.contains(char::is_whitespace) {
+                            status = Some(StatusMessage::error("⚠️ A tag is a single word."));
+                        } else if let Some(task) = tasks.get_mut(selected) {
+                            task.add_tag(tag);
+                            tag_selected = all_tags(&tasks)
+                                .iter()
+                                .position(|t| t.eq_ignore_ascii_case(tag))
+                                .unwrap_or(0);
+                            status = Some(StatusMessage::info(format!("Added #{tag}, saved.")));
+                            save_tasks(&tasks, &config).unwrap_or_else(|e| write_error = Some(e));
+                            last_change = Some(Instant::now());
+                        }
+                        mode = "tags";
+                    }
+                    KeyCode::Esc => {
+                        input.clear();
+                        mode = "tags";
+                    }
+                    code => input.handle_key(code),
+                },
+                "view" => match code {
+                    KeyCode::Char('j') | KeyCode::Down if grouped => {
+                        if group_row + 1 < rows.len() {
+                            group_row += 1;
+                        } else if config.wrap_navigation {
+                            group_row = 0;
+                        }
+                        if let Some(&GroupRow::Task(i)) = rows.get(group_row) {
+                            selected = i;
+                        }
+                    }
+                    KeyCode::Char('k') | KeyCode::Up if grouped => {
+                        if group_row > 0 {
+                            group_row -= 1;
+                        } else if config.wrap_navigation {
+                            group_row = rows.len().saturating_sub(1);
+                        }
+                        if let Some(&GroupRow::Task(i)) = rows.get(group_row) {
+                            selected = i;
+                        }
+                    }
+                    KeyCode::Enter | KeyCode::Char(' ') if on_header => {
+                        if let Some(GroupRow::Header(tag, _)) = rows.get(group_row)
+                            && !collapsed.remove(tag)
+                        {
+                            collapsed.insert(tag.clone());
+                        }
+                    }
+                    KeyCode::Char('G') => {
+                        config.view.group_by_tag = !config.view.group_by_tag;
+                        group_row = usize::MAX;
+                        status = Some(match save_config(&config, &["view.group_by_tag"]) {
+                            Ok(()) if config.view.group_by_tag => {
+                                StatusMessage::info("Grouped by tag; Enter on a heading folds it.")
+                            }
+                            Ok(()) => StatusMessage::info("Grouping off."),
+                            Err(e) => StatusMessage::error(format!("Could not save config: {e}")),
+                        });
+                    }
+                    KeyCode::Char('m') if selected < tasks.len() => {
+                        move_files = tasks_files_nearby(config.tasks_file());
+                        move_selected = 0;
+                        mode = "move_file";
+                    }
+                    KeyCode::Char('#') if selected < tasks.len() => {
+                        tag_selected = 0;
+                        mode = "tags";
+                    }
+                    KeyCode::Char('o') | KeyCode::Char('i')
+                        if code == KeyCode::Char('i')
+                            || key.modifiers.contains(KeyModifiers::CONTROL) =>
+                    {
+                        let url = tasks.get(selected).and_then(|t| {
+                            if code == KeyCode::Char('i') {
+                                t.issue_link()
+                            } else {
+                                first_url(&t.description).map(str::to_string)
+                            }
+                        });
+                        match url {
+                            Some(url) if config.url_opener_in_terminal => {
+                                suspend_tui(terminal)?;
+                                let result = open_url(&url, config.url_opener.as_deref(), true);
+                                resume_tui(terminal)?;
+                                if let Err(e) = result {
+                                    status = Some(StatusMessage::error(format!(
+                                        "Could not open {url}: {e}"
+                                    )));
+                                }
+                            }
+                            Some(url) => {
+                                status = Some(
+                                    match open_url(&url, config.url_opener.as_deref(), false) {
+                                        Ok(()) => StatusMessage::info(format!("Opened {url}")),
+                                        Err(e) => StatusMessage::error(format!(
+                                            "Could not open {url}: {e}"
+                                        )),
+                                    },
+                                );
+                            }
+                            None if code == KeyCode::Char('i') && ISSUE_TRACKER.get().is_none() => {
+                                status = Some(StatusMessage::error(
+                                    "No issue tracker: set issue_url in tasksmanager.toml.",
+                                ))
+                            }
+                            None if code == KeyCode::Char('i') => {
+                                status = Some(StatusMessage::error(
+                                    "No issue reference in the selected task.",
+                                ))
+                            }
+                            None => {
+                                status = Some(StatusMessage::error("No URL in the selected task."))
+                            }
+                        }
+                    }
+                    KeyCode::Char('q') => break,
+                    KeyCode::Char('j') | KeyCode::Down => {
+                        if let Some(pos) = visible.iter().position(|&i| i == selected) {
+                            if pos + 1 < visible.len() {
+                                selected = visible[pos + 1];
+                            } else if config.wrap_navigation {
+                                selected = visible[0];
+                            }
+                        }
+                    }
+                    KeyCode::Char('k') | KeyCode::Up => {
+                        if let Some(pos) = visible.iter().position(|&i| i == selected) {
+                            if pos > 0 {
+                                selected = visible[pos - 1];
+                            } else if config.wrap_navigation {
+                                selected = visible[visible.len() - 1];
+                            }
+                        }
+                    }
+                    KeyCode::Char(bracket @ (']' | '[')) if !visible.is_empty() => {
+                        match jump_working(&tasks, &visible, selected, bracket == ']') {
+                            Some(next) => selected = next,
+                            None => {
+                                status = Some(StatusMessage::info("No Working tasks in view."));
+                            }
+                        }
+                    }
+                    KeyCode::Char('?') => cheat_page += 1,
+                    KeyCode::Char('r') => match read_tasks(&mut config) {
+                        Ok((frontmatter, mut reloaded)) => {
+                            if let Some(command) =
+                                frontmatter.as_ref().and_then(|f| f.get("test_command"))
+                            {
+                                test_command = command;
+                            }
+                            config.frontmatter = frontmatter;
+                            if config.dedup_on_load {
+                                dedup_tasks(&mut reloaded);
+                            }
+                            selected = surviving_selection(&tasks, selected, &reloaded);
+                            tasks = reloaded;
+                            status = Some(StatusMessage::info(format!(
+                                "Reloaded {} task(s) from {}",
+                                tasks.len(),
+                                config.tasks_file()
+                            )));
+                        }
+                        Err(e) => status = Some(StatusMessage::error(e)),
+                    },
+                    KeyCode::Char('I') => {
+                        input.set("tasks.json".to_string(), true);
+                        mode = "import";
+                    }
+                    KeyCode::Char('v') => table_view = !table_view,
+                    KeyCode::Char('O') => split_view = !split_view,
+                    KeyCode::Char('X') => {
+                        trash_selected = 0;
+                        mode = "trash";
+                    }
+                    KeyCode::Char('L') => match tcr_log(TCR_LOG_LIMIT) {
+                        Ok(commits) => {
+                            tcr_commits = commits;
+                            log_selected = 0;
+                            log_stat = None;
+                            mode = "tcr_log";
+                        }
+                        Err(e) => status = Some(StatusMessage::error(format!("⚠️ {e}"))),
+                    },
+                    KeyCode::Char('B') => {
+                        if batch_mode {
+                            batch_mode = false;
+                            status = Some(StatusMessage::info("Batch TCR off."));
+                        } else if let Some(first) = tasks
+                            .get(selected)
+                            .filter(|t| t.status == Status::Working)
+                            .map(|_| selected)
+                            .or_else(|| next_working(&tasks, selected))
+                        {
+                            selected = first;
+                            batch_mode = true;
+                            status = Some(StatusMessage::info(
+                                "Batch TCR on: t completes the selected Working task and moves to the next.",
+                            ));
+                        } else {
+                            status = Some(StatusMessage::error("No Working tasks to batch."));
+                        }
+                    }
+                    KeyCode::Char('H') => mode = "help",
+                    KeyCode::Char('/') => {
+                        input.set(filter_query.clone(), true);
+                        mode = "filter";
+                    }
+                    KeyCode::Char('d') if selected < tasks.len() => {
+                        // Subtasks go with their parent and come back with it from the trash.
+                        let deleted = Deleted {
+                            tasks: tasks.drain(subtree(&tasks, selected)).collect(),
+                            index: selected,
+                            after: selected.checked_sub(1).map(|above| tasks[above].id),
+                        };
+                        let subtasks = deleted.tasks.len() - 1;
+                        trash.push_front(deleted);
+                        trash.truncate(config.trash_size);
+                        if config.persist_trash {
+                            save_trash(&trash).unwrap_or_else(|e| write_error = Some(e));
+                        }
+                        selected = selected.saturating_sub(1);
+                        save_tasks(&tasks, &config).unwrap_or_else(|e| write_error = Some(e));
+                        last_change = Some(Instant::now());
+                        status = Some(StatusMessage::info(match subtasks {
+                            0 => "Task deleted, saved.".to_string(),
+                            n => {
+                                format!("Task and {n} subtask(s) deleted, saved. X restores them.")
+                            }
+                        }));
+                        last_action = Some(Repeat::Delete);
+                    }
+                    KeyCode::Char('.') => {
+                        status = Some(StatusMessage::info(
+                            "Nothing to repeat yet: . repeats a delete, duplicate, toggle or *.",
+                        ));
+                    }
+                    KeyCode::Char('a') => {
+                        input.clear();
+                        mode = "input";
+                    }
+                    KeyCode::Char('N') => {
+                        if config.templates.is_empty() {
+                            status = Some(StatusMessage::error(
+                                "⚠️ No templates; add [[templates]] to tasksmanager.toml.",
+                            ));
+                        } else {
+                            template_selected = 0;
+                            mode = "templates";
+                        }
+                    }
+                    KeyCode::Char('e') => {
+                        if let Some(task) = tasks.get(selected) {
+                            input.set(task.to_line(), false);
+                            mode = "edit";
+                        }
+                    }
+                    KeyCode::Char('A') => {
+                        if let Some(task) = tasks.get(selected) {
+                            input.set(task.to_line(), true);
+                            mode = "edit";
+                        }
+                    }
+                    KeyCode::Char('y') if selected < tasks.len() => {
+                        // The copy goes after the subtasks, which stay with the original.
+                        let copy = tasks[selected].duplicate(next_id(&tasks));
+                        selected = insert_sibling(&mut tasks, selected, copy);
+                        save_tasks(&tasks, &config).unwrap_or_else(|e| write_error = Some(e));
+                        last_change = Some(Instant::now());
+                        status = Some(StatusMessage::info(
+                            "Task duplicated, saved. e edits the copy.",
+                        ));
+                        last_action = Some(Repeat::Duplicate);
+                    }
+                    KeyCode::Tab if selected < tasks.len() => {
+                        if selected == 0 || tasks[selected - 1].depth < tasks[selected].depth {
+                            status = Some(StatusMessage::error(
+                                "Nothing above to indent under: a subtask goes one level below the task before it.",
+                            ));
+                        } else {
+                            let range = subtree(&tasks, selected);
+                            for task in &mut tasks[range] {
+                                task.depth += 1;
+                            }
+                            save_tasks(&tasks, &config).unwrap_or_else(|e| write_error = Some(e));
+                            last_change = Some(Instant::now());
+                        }
+                    }
+                    KeyCode::BackTab if selected < tasks.len() => {
+                        if tasks[selected].depth == 0 {
+                            status = Some(StatusMessage::error("Already a top-level task."));
+                        } else {
+                            let range = subtree(&tasks, selected);
+                            for task in &mut tasks[range] {
+                                task.depth -= 1;
+                            }
+                            save_tasks(&tasks, &config).unwrap_or_else(|e| write_error = Some(e));
+                            last_change = Some(Instant::now());
+                        }
+                    }
+                    KeyCode::Char('*') if selected < tasks.len() => {
+                        let task = &mut tasks[selected];
+                        task.review = match &last_action {
+                            Some(Repeat::Review(flag)) if repeating => *flag,
+                            _ => !task.review,
+                        };
+                        last_action = Some(Repeat::Review(task.review));
+                        status = Some(StatusMessage::info(if task.review {
+                            "Flagged for review, saved. Filter with / *"
+                        } else {
+                            "Review flag cleared, saved."
+                        }));
+                        save_tasks(&tasks, &config).unwrap_or_else(|e| write_error = Some(e));
+                        last_change = Some(Instant::now());
+                    }
+                    KeyCode::Char('P') if selected < tasks.len() => {
+                        // Start on the current value, or on "none" if there is none.
+                        points_selected = match tasks[selected].points {
+                            Some(points) => POINT_VALUES
+                                .iter()
+                                .position(|&p| p == points)
+                                .unwrap_or(POINT_VALUES.len()),
+                            None => POINT_VALUES.len() + 1,
+                        };
+                        mode = "points";
+                    }
+                    KeyCode::Char('p') if selected < tasks.len() => {
+                        let task = &mut tasks[selected];
+                        task.pinned = !task.pinned;
+                        status = Some(StatusMessage::info(if task.pinned {
+                            "Pinned to the top, saved."
+                        } else {
+                            "Unpinned, saved."
+                        }));
+                        save_tasks(&tasks, &config).unwrap_or_else(|e| write_error = Some(e));
+                        last_change = Some(Instant::now());
+                    }
+                    KeyCode::Char('C') if selected < tasks.len() => {
+                        let task = &tasks[selected];
+                        status = Some(StatusMessage::info(if current_task == Some(task.id) {
+                            current_task = None;
+                            "No current task: t commits for the selected one.".to_string()
+                        } else {
+                            current_task = Some(task.id);
+                            format!(
+                                "Current task: \"{}\". t commits for it wherever the cursor is.",
+                                task.description
+                            )
+                        }));
+                    }
+                    KeyCode::Char('h') if selected < tasks.len() => {
+                        hidden.extend(tasks[subtree(&tasks, selected)].iter().map(|t| t.id));
+                        status = Some(StatusMessage::info(format!(
+                            "Hidden until restart ({} task(s) hidden, U shows them again).",
+                            hidden.len()
+                        )));
+                    }
+                    KeyCode::Char('U') => {
+                        status = Some(StatusMessage::info(match hidden.len() {
+                            0 => "No hidden tasks.".to_string(),
+                            n => format!("Showing {n} hidden task(s) again."),
+                        }));
+                        hidden.clear();
+                    }
+                    KeyCode::Char('Y') if selected < tasks.len() => {
+                        let task = &tasks[selected];
+                        let text = if config.copy_format == "markdown" {
+                            format!("- [{}] {}", task.status.marker(), task.to_line())
+                        } else {
+                            task.description.clone()
+                        };
+                        match copy_to_clipboard(&mut clipboard, &text) {
+                            Ok(()) => status = Some(StatusMessage::info(format!("Copied: {text}"))),
+                            Err(e) => {
+                                suspend_tui(terminal)?;
+                                println!("Clipboard unavailable ({e}). Copy the task from here:");
+                                println!();
+                                println!("{text}");
+                                println!();
+                                println!("Press Enter to return to UI...");
+                                let _ = io::stdin().read_line(&mut String::new());
+                                resume_tui(terminal)?;
+                            }
+                        }
+                    }
+                    KeyCode::Char(key @ ('K' | 'J')) if selected < tasks.len() => {
+                        match move_task(&mut tasks, selected, key == 'K') {
+                            Some(moved) => {
+                                selected = moved;
+                                save_tasks(&tasks, &config)
+                                    .unwrap_or_else(|e| write_error = Some(e));
+                                last_change = Some(Instant::now());
+                            }
+                            None => {
+                                status =
+                                    Some(StatusMessage::info("Nothing to swap with at this level."))
+                            }
+                        }
+                    }
+                    KeyCode::Char('S') if selected < tasks.len() => {
+                        input.clear();
+                        mode = "split";
+                    }
+                    KeyCode::Char('T') => {
+                        input.set(test_command.clone(), true);
+                        mode = "test";
+                    }
+                    KeyCode::Char('t')
+                        if key.modifiers.contains(KeyModifiers::CONTROL)
+                            && selected < tasks.len() =>
+                    {
+                        input.set(
+                            tasks[selected].test_command.clone().unwrap_or_default(),
+                            true,
+                        );
+                        mode = "task_test";
+                    }
+                    KeyCode::Char('W') if selected < tasks.len() => {
+                        input.set(tasks[selected].cwd.clone().unwrap_or_default(), true);
+                        mode = "task_cwd";
+                    }
+                    KeyCode::Char('!') => {
+                        input.set(config.tasks_file().to_string(), true);
+                        mode = "keep_path";
+                    }
+                    KeyCode::Char('t') if repo_missing => mode = "git_init",
+                    KeyCode::Char('t') if test_job.is_some() => {
+                        status = Some(StatusMessage::info("Tests are already running."));
+                    }
+                    KeyCode::Char('t') if config.confirm_test && !confirmed => {
+                        mode = "test_confirm"
+                    }
+                    KeyCode::Char('t') if split_view || multiplexer.is_some() => {
+                        tcr_task = tcr_target(&tasks, current_task, selected, batch_mode);
+                        let (command, dir) = task_test(&tasks, tcr_task, &test_command);
+                        // The split view streams into its own pane already.
+                        let (job, message) = match multiplexer.filter(|_| !split_view) {
+                            Some(mux) => (
+                                TestJob::in_pane(&command, dir, mux),
+                                format!("Running tests in a {} pane...", mux.name()),
+                            ),
+                            None => (
+                                TestJob::spawn(&command, dir),
+                                "Running tests...".to_string(),
+                            ),
+                        };
+                        test_job = Some(job);
+                        status = Some(StatusMessage::info(message));
+                    }
+                    KeyCode::Char('t') => {
+                        tcr_task = tcr_target(&tasks, current_task, selected, batch_mode);
+                        let (command, dir) = task_test(&tasks, tcr_task, &test_command);
+                        suspend_tui(terminal)?;
+                        finished_run = Some(run_test_command(
+                            &command,
+                            dir.as_deref(),
+                            &Arc::default(),
+                            true,
+                        ));
+                        println!("Press Enter to return to UI...");
+                        let _ = io::stdin().read_line(&mut String::new());
+                        resume_tui(terminal)?;
+                    }
+                    // Terminals without key release reporting send a held Enter as a
+                    // stream of presses; only the first one cycles the status.
+                    KeyCode::Enter if config.enter_action == "edit" && selected < tasks.len() => {
+                        input.set(tasks[selected].to_line(), false);
+                        mode = "edit";
+                    }
+                    KeyCode::Enter if config.enter_action == "detail" && selected < tasks.len() => {
+                        mode = "detail";
+                    }
+                    KeyCode::Enter | KeyCode::Char(' ')
+                        if !repeating
+                            && last_toggle
+                                .is_some_and(|at| at.elapsed() < TOGGLE_REPEAT_WINDOW) =>
+                    {
+                        last_toggle = Some(Instant::now());
+                    }
+                    KeyCode::Enter | KeyCode::Char(' ') => {
+                        last_toggle = Some(Instant::now());
+                        if let Some(task) = tasks.get_mut(selected) {
+                            let next = match &last_action {
+                                Some(Repeat::SetStatus(status)) if repeating => status.clone(),
+                                _ => task.status.next(),
+                            };
+                            last_action = Some(Repeat::SetStatus(next.clone()));
+                            if config.done_note && next == Status::Done {
+                                note_task = Some(task.id);
+                                input.clear();
+                                mode = "done_note";
+                            }
+                            task.set_status(next, now);
+                            save_tasks(&tasks, &config).unwrap_or_else(|e| write_error = Some(e));
+                            last_change = Some(Instant::now());
+                        }
+                    }
+                    KeyCode::Char('D') => {
+                        config.relative_dates = !config.relative_dates;
+                        status = Some(match save_config(&config, &["relative_dates"]) {
+                            Ok(()) if config.relative_dates => {
+                                StatusMessage::info("Showing relative due dates.")
+                            }
+                            Ok(()) => StatusMessage::info("Showing absolute due dates."),
+                            Err(e) => StatusMessage::error(format!("Could not save config: {e}")),
+                        });
+                    }
+                    KeyCode::Char('=') => {
+                        config.item_spacing = if config.item_spacing == 0 { 1 } else { 0 };
+                        status = Some(match save_config(&config, &["item_spacing"]) {
+                            Ok(()) if config.item_spacing > 0 => {
+                                StatusMessage::info("A blank line between tasks.")
+                            }
+                            Ok(()) => StatusMessage::info("Tasks listed without spacing."),
+                            Err(e) => StatusMessage::error(format!("Could not save config: {e}")),
+                        });
+                    }
+                    KeyCode::Char('E') => {
+                        input.set(filter_query.clone(), true);
+                        mode = "export";
+                    }
+                    KeyCode::Char('F') if tasks.iter().any(|t| t.is_overdue(today)) => {
+                        input.set("1d".to_string(), true);
+                        mode = "defer";
+                    }
+                    KeyCode::Char('F') => {
+                        status = Some(StatusMessage::info("No overdue tasks to reschedule."));
+                    }
+                    KeyCode::Char('c') => mode = "commit_confirm",
+                    KeyCode::Char('w') => {
+                        save_tasks(&tasks, &config).unwrap_or_else(|e| write_error = Some(e));
+                        // A `commit_on_change` commit still waiting out its debounce goes now.
+                        status = Some(if config.commit_on_change && last_change.is_some() {
+                            match config.vcs().commit(
+                                "Update task list",
+                                &config.commit_paths(),
+                                config.no_verify,
+                            ) {
+                                Ok(()) => {
+                                    last_change = None;
+                                    StatusMessage::info(format!(
+                                        "Saved {} and committed it.",
+                                        config.tasks_file()
+                                    ))
+                                }
+                                Err(e) => StatusMessage::error(format!(
+                                    "Saved {}, but the commit failed: {e}",
+                                    config.tasks_file()
+                                )),
+                            }
+                        } else {
+                            StatusMessage::info(format!("Saved {}.", config.tasks_file()))
+                        });
+                    }
+                    KeyCode::Char('x') if selected < tasks.len() => {
+                        let children = subtree(&tasks, selected);
+                        let children = &tasks[children.start + 1..children.end];
+                        // Done unless every subtask already is, then back to Pending.
+                        let target = if children.iter().all(|t| t.status == Status::Done) {
+                            Status::Pending
+                        } else {
+                            Status::Done
+                        };
+                        let changing = children.iter().filter(|t| t.status != target).count();
+                        bulk_ids = children.iter().map(|t| t.id).collect();
+                        if bulk_ids.is_empty() {
+                            status =
+                                Some(StatusMessage::info("The selected task has no subtasks."));
+                        } else if changing > SUBTASK_CONFIRM_OVER {
+                            bulk_status = Some(target);
+                            mode = "bulk_confirm";
+                        } else {
+                            let changed = set_statuses(&mut tasks, &bulk_ids, &target, now);
+                            save_tasks(&tasks, &config).unwrap_or_else(|e| write_error = Some(e));
+                            last_change = Some(Instant::now());
+                            let (done, total) =
+                                subtask_progress(&tasks, selected).unwrap_or_default();
+                            status = Some(StatusMessage::info(format!(
+                                "Set {changed} subtask(s) of \"{}\" to {target:?}, {done}/{total} done, saved.",
+                                tasks[selected].description
+                            )));
+                        }
+                    }
+                    KeyCode::Char('M') if visible.is_empty() => {
+                        status = Some(StatusMessage::info("No tasks in the view to set."));
+                    }
+                    KeyCode::Char('M') => {
+                        // Ids, so the set stays the one shown even if the filter changes.
+                        bulk_ids = visible.iter().map(|&i| tasks[i].id).collect();
+                        bulk_status = None;
+                        mode = "bulk_status";
+                    }
+                    KeyCode::Char('%') => {
+                        stats = Some(Stats::collect(&tasks, today));
+                        mode = "stats";
+                    }
+                    KeyCode::Char('s') => {
+                        config.view.sort = config.view.sort.next();
+                        status = Some(match save_config(&config, &["view.sort"]) {
+                            Ok(()) => StatusMessage::info(format!(
+                                "Sorted by {}.",
+                                config.view.sort.label()
+                            )),
+                            Err(e) => StatusMessage::error(format!("Could not save config: {e}")),
+                        });
+                    }
+                    KeyCode::Char('R') => {
+                        config.view = ViewSettings::default();
+                        filter_query.clear();
+                        view_filter = Filter::default();
+                        status = Some(
+                            match save_config(
+                                &config,
+                                &["view.filter", "view.sort", "view.group_by_tag"],
+                            ) {
+                                Ok(()) => StatusMessage::info(
+                                    "View reset: no filter, file order, no grouping.",
+                                ),
+                                Err(e) => {
+                                    StatusMessage::error(format!("Could not save config: {e}"))
+                                }
+                            },
+                        );
+                    }
+                    KeyCode::Char('o') if test_output.is_empty() => {
+                        status = Some(StatusMessage::info("No test output yet; t runs the tests."));
+                    }
+                    KeyCode::Char('o') => {
+                        output_scroll = 0;
+                        mode = "output";
+                    }
+                    _ => {}
+                },
+                "output" => match key.code {
+                    KeyCode::Char('j') | KeyCode::Down => {
+                        output_scroll = output_scroll.saturating_add(1);
+                    }
+                    KeyCode::Char('k') | KeyCode::Up => {
+                        output_scroll = output_scroll.saturating_sub(1);
+                    }
+                    KeyCode::Char('f') => {
+                        config.full_output = !config.full_output;
+                        output_scroll = 0;
+                        if let Err(e) = save_config(&config, &["full_output"]) {
+                            status =
+                                Some(StatusMessage::error(format!("Could not save config: {e}")));
+                        }
+                    }
+                    KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('o') => mode = "view",
+                    _ => {}
+                },
+                "input" | "template_input" => match key.code {
+                    KeyCode::Enter if invalid_due(&input.text).is_some() => {
+                        status = Some(due_error(&input.text));
+                    }
+                    KeyCode::Enter
+                        if mode == "template_input"
+                            && next_placeholder(&input.text, 0).is_some() =>
+                    {
+                        status = Some(StatusMessage::error(
+                            "⚠️ Fill in the {placeholders} first (Tab jumps to them).",
+                        ));
+                    }
+                    KeyCode::Tab if mode == "template_input" => {
+                        // Wraps around to the first placeholder after the last one.
+                        if let Some(placeholder) = next_placeholder(&input.text, input.cursor + 1)
+                            .or_else(|| next_placeholder(&input.text, 0))
+                        {
+                            input.cursor = placeholder.start;
+                        }
+                    }
+                    KeyCode::Char(c) if mode == "template_input" => {
+                        // Typing at a placeholder replaces it.
+                        if let Some(placeholder) = next_placeholder(&input.text, input.cursor)
+                            .filter(|p| p.start == input.cursor)
+                        {
+                            let bytes = input.byte_index(placeholder.start)
+                                ..input.byte_index(placeholder.end);
+                            input.text.replace_range(bytes, "");
+                        }
+                        input.handle_key(KeyCode::Char(c));
+                    }
+                    KeyCode::Enter => {
+                        if let Some((mut task, warnings)) =
+                            Task::new(input.take(), config.max_description_length)
+                        {
+                            task.id = next_id(&tasks);
+                            let message = if task.notes.is_empty() {
+                                "Task added, saved."
+                            } else {
+                                "Task added with the overflow as a note, saved."
+                            };
+                            status = Some(noting(message, &warnings, &config));
+                            tasks.push(task);
+                            save_tasks(&tasks, &config).unwrap_or_else(|e| write_error = Some(e));
+                            last_change = Some(Instant::now());
+                            if config.multi_add {
+                                mode = "input";
+                            } else {
+                                selected = tasks.len() - 1;
+                                mode = "view";
+                            }
+                        } else {
+                            status =
+                                Some(StatusMessage::error("⚠️ Task description cannot be empty."));
+                        }
+                    }
+                    KeyCode::Esc => mode = "view",
+                    code => input.handle_key(code),
+                },
+                "edit" => match key.code {
+                    // The task keeps its due date until the new one reads as a date.
+                    KeyCode::Enter if invalid_due(&input.text).is_some() => {
+                        status = Some(due_error(&input.text));
+                    }
+                    KeyCode::Enter => {
+                        if let Some(task) = tasks.get_mut(selected) {
+                            if let Some((mut updated, warnings)) =
+                                Task::new(input.take(), config.max_description_length)
+                            {
+                                let message = if updated.notes.is_empty() {
+                                    "Task updated, saved."
+                                } else {
+                                    "Task updated with the overflow as a note, saved."
+                                };
+                                status = Some(noting(message, &warnings, &config));
+                                // Keep what the editable text does not carry.
+                                updated.id = task.id;
+                                updated.status = task.status.clone();
+                                updated.depth = task.depth;
+                                updated.started = task.started;
+                                updated.notes.append(&mut task.notes);
+                                *task = updated;
+                                save_tasks(&tasks, &config)
+                                    .unwrap_or_else(|e| write_error = Some(e));
+                                last_change = Some(Instant::now());
+                            } else {
+                                status = Some(StatusMessage::error(
+                                    "⚠️ Updated description cannot be empty.",
+                                ));
+                            }
+                        }
+                    }
+                    KeyCode::Esc => mode = "view",
+                    code => input.handle_key(code),
+                },
+                "split" => match key.code {
+                    KeyCode::Enter => {
+                        if let Some((mut task, warnings)) =
+                            Task::new(input.take(), config.max_description_length)
+                        {
+                            if let Some(original) = tasks.get(selected) {
+                                task.inherit_tags(original);
+                                task.priority = task.priority.or(original.priority);
+                            }
+                            task.id = next_id(&tasks);
+                            if selected < tasks.len() {
+                                selected = insert_sibling(&mut tasks, selected, task);
+                            } else {
+                                selected = tasks.len();
+                                tasks.push(task);
+                            }
+                            save_tasks(&tasks, &config).unwrap_or_else(|e| write_error = Some(e));
+                            last_change = Some(Instant::now());
+                            status = Some(noting("Task split, saved.", &warnings, &config));
+                            mode = "view";
+                        } else {
+                            status =
+                                Some(StatusMessage::error("⚠️ Task description cannot be empty."));
+                        }
+                    }
+                    KeyCode::Esc => mode = "view",
+                    code => input.handle_key(code),
+                },
+                "filter" => match key.code {
+                    KeyCode::Enter => {
+                        filter_query = input.take().trim().to_string();
+                        view_filter = Filter::parse(&filter_query);
+                        config.view.filter = filter_query.clone();
+                        if let Err(e) = save_config(&config, &["view.filter"]) {
+                            status =
+                                Some(StatusMessage::error(format!("Could not save config: {e}")));
+                        }
+                        mode = "view";
+                    }
+                    KeyCode::Esc => mode = "view",
+                    code => input.handle_key(code),
+                },
+                "defer" => match key.code {
+                    KeyCode::Enter => match parse_offset(input.text.trim()) {
+                        Some(offset) => {
+                            input.clear();
+                            let mut moved = 0;
+                            for task in tasks.iter_mut().filter(|t| t.is_overdue(today)) {
+                                task.due = task.due.map(|due| due + offset);
+                                moved += 1;
+                            }
+                            if moved > 0 {
+                                save_tasks(&tasks, &config)
+                                    .unwrap_or_else(|e| write_error = Some(e));
+                                last_change = Some(Instant::now());
+                            }
+                            status = Some(StatusMessage::info(format!(
+                                "Rescheduled {moved} overdue task(s) by {} day(s).",
+                                offset.num_days()
+                            )));
+                            mode = "view";
+                        }
+                        None => {
+                            status = Some(StatusMessage::error(
+                                "Give the delay as days or weeks, e.g. 1d, 3d or 2w.",
+                            ));
+                        }
+                    },
+                    KeyCode::Esc => mode = "view",
+                    code => input.handle_key(code),
+                },
+                "export" => match key.code {
+                    KeyCode::Enter => {
+                        let filter = Filter::parse(&input.take());
+                        status = Some(match export_to_json(&tasks, &filter) {
+                            Ok(count) => StatusMessage::info(format!(
+                                "Exported {count} task(s) to {}",
+                                filter.export_file()
+                            )),
+                            Err(e) => StatusMessage::error(format!("Export failed: {e}")),
+                        });
+                        mode = "view";
+                    }
+                    KeyCode::Esc => mode = "view",
+                    code => input.handle_key(code),
+                },
+                "task_cwd" | "task_test" => match key.code {
+                    KeyCode::Enter
+                        if mode == "task_cwd"
+                            && !input.text.trim().is_empty()
+                            && !Path::new(input.text.trim()).is_dir() =>
+                    {
+                        status = Some(StatusMessage::error(format!(
+                            "⚠️ {} is not a directory.",
+                            input.text.trim()
+                        )));
+                    }
+                    KeyCode::Enter => {
+                        let value = input.take().trim().to_string();
+                        let value = (!value.is_empty()).then_some(value);
+                        if let Some(task) = tasks.get_mut(selected) {
+                            let message = match (mode, &value) {
+                                ("task_cwd", Some(dir)) => {
+                                    format!("t runs this task's tests in {dir}, saved.")
+                                }
+                                ("task_cwd", None) => {
+                                    "t runs this task's tests where the app runs, saved."
+                                        .to_string()
+                                }
+                                (_, Some(command)) => {
+                                    format!("t runs `{command}` for this task, saved.")
+                                }
+                                (_, None) => {
+                                    "This task uses the global test command, saved.".to_string()
+                                }
+                            };
+                            if mode == "task_cwd" {
+                                task.cwd = value;
+                            } else {
+                                task.test_command = value;
+                            }
+                            save_tasks(&tasks, &config).unwrap_or_else(|e| write_error = Some(e));
+                            last_change = Some(Instant::now());
+                            status = Some(StatusMessage::info(message));
+                        }
+                        mode = "view";
+                    }
+                    KeyCode::Esc => mode = "view",
+                    code => input.handle_key(code),
+                },
+                "keep_path" => match key.code {
+                    KeyCode::Enter => {
+                        let path = input.take().trim().to_string();
+                        let pattern = root_pattern(Path::new(&path)).filter(|_| !path.is_empty());
+                        status = Some(match pattern {
+                            Some(mut pattern) => {
+                                if Path::new(&path).is_dir() {
+                                    pattern.push('/');
+                                }
+                                match kept_paths.iter().position(|(kept, _)| *kept == pattern) {
+                                    Some(at) => {
+                                        kept_paths.remove(at);
+                                    }
+                                    None => kept_paths.push((pattern, path)),
+                                }
+                                if kept_paths.is_empty() {
+                                    StatusMessage::info("A failed run reverts everything again.")
+                                } else {
+                                    StatusMessage::info(format!(
+                                        "Kept from TCR reverts this session: {}.",
+                                        kept_list(&kept_paths)
+                                    ))
+                                }
+                            }
+                            None => StatusMessage::error(format!(
+                                "⚠️ {path} is not inside the git repository."
+                            )),
+                        });
+                        mode = "view";
+                    }
+                    KeyCode::Esc => mode = "view",
+                    code => input.handle_key(code),
+                },
+                "test" => match key.code {
+                    KeyCode::Enter => {
+                        test_command = input.take();
+                        if let Some(frontmatter) = &mut config.frontmatter
+                            && frontmatter.update("test_command", test_command.trim())
+                        {
+                            save_tasks(&tasks, &config).unwrap_or_else(|e| write_error = Some(e));
+                            last_change = Some(Instant::now());
+                        }
+                        mode = "view";
+                    }
+                    KeyCode::Esc => mode = "view",
+                    code => input.handle_key(code),
+                },
+                _ => {}
+            }
+        }
+    }
+
+    // Cleared and held to the end, so a hangup from here on waits for this
+    // teardown and does not run the last one published.
+    let mut pending = teardown.lock().unwrap();
+    pending.take();
+    state.record(&snapshot_key, &tasks, selected, current_task);
+    Teardown::new(&config, &state, last_change.is_some()).run()
+}
+
+/// Lets the user choose which of `files` to open, for `startup = "picker"`.
+/// Files that no longer exist are left out, though `files`' last entry is
+/// always offered as it is created when missing. `None` when left with Esc.
+fn pick_file(
+    terminal: &mut Terminal<CrosstermBackend<std::io::Stdout>>,
+    files: &[String],
+    shutdown: &AtomicBool,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let files: Vec<&String> = files
+        .iter()
+        .enumerate()
+        .filter(|&(i, file)| i + 1 == files.len() || Path::new(file).is_file())
+        .map(|(_, file)| file)
+        .collect();
+    if let [file] = files[..] {
+        return Ok(Some(file.clone()));
+    }
+    let mut list_state = ListState::default();
+    list_state.select(Some(0));
+    loop {
+        if shutdown.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        terminal.draw(|f| {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(0), Constraint::Length(1)])
+                .split(f.size());
+            let items: Vec<ListItem> = files
+                .iter()
+                .map(|file| ListItem::new(file.as_str()))
+                .collect();
+            let list = List::new(items)
+                .highlight_style(
+                    Style::default()
+                        .fg(Color::Yellow)
+                        .add_modifier(Modifier::BOLD),
+                )
+                .highlight_symbol("> ")
+                .block(
+                    Block::default()
+                        .title("Open a tasks file")
+                        .borders(Borders::ALL),
+                );
+            f.render_stateful_widget(list, chunks[0], &mut list_state);
+            let hints: Vec<String> = PICKER_KEYS
+                .iter()
+                .map(|(key, action)| format!("{key}: {action}"))
+                .collect();
+            f.render_widget(
+                Paragraph::new(hints.join("  ")).style(Style::default().fg(Color::DarkGray)),
+                chunks[1],
+            );
+        })?;
+        if event::poll(Duration::from_millis(250))?
+            && let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+        {
+            let selected = list_state.selected().unwrap_or(0);
+            match key.code {
+                KeyCode::Char('j') | KeyCode::Down => {
+                    list_state.select(Some((selected + 1).min(files.len() - 1)));
+                }
+                KeyCode::Char('k') | KeyCode::Up => {
+                    list_state.select(Some(selected.saturating_sub(1)));
+                }
+                KeyCode::Enter => return Ok(Some(files[selected].clone())),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(None);
+                }
+                KeyCode::Esc | KeyCode::Char('q') => return Ok(None),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Shows each conflict in the tasks file with our side next to theirs, and
+/// writes the file once every conflict has a side kept. Returns how many
+/// were resolved, or `None` when left with Esc: the file is untouched and
+/// loading it still fails on the markers.
+fn resolve_conflicts(
+    terminal: &mut Terminal<CrosstermBackend<std::io::Stdout>>,
+    path: &str,
+    regions: &[Region],
+    shutdown: &AtomicBool,
+) -> Result<Option<usize>, Box<dyn Error>> {
+    let conflicts: Vec<(&[String], &[String])> = regions
+        .iter()
+        .filter_map(|region| match region {
+            Region::Conflict { ours, theirs } => Some((ours.as_slice(), theirs.as_slice())),
+            Region::Clean(_) => None,
+        })
+        .collect();
+    let mut choices: Vec<Side> = Vec::new();
+    while let Some(&(ours, theirs)) = conflicts.get(choices.len()) {
+        if shutdown.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        terminal.draw(|f| {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([
+                    Constraint::Length(1),
+                    Constraint::Min(0),
+                    Constraint::Length(1),
+                ])
+                .split(f.size());
+            let title = format!(
+                "{path} has Git conflict markers: conflict {} of {}",
+                choices.len() + 1,
+                conflicts.len()
+            );
+            f.render_widget(
+                Paragraph::new(title).style(Style::default().fg(Color::Yellow)),
+                chunks[0],
+            );
+            let sides = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .split(chunks[1]);
+            for (lines, title, area) in [(ours, "Ours", sides[0]), (theirs, "Theirs", sides[1])] {
+                let text: Vec<Spans> = lines
+                    .iter()
+                    .map(|line| Spans::from(line.as_str()))
+                    .collect();
+                f.render_widget(
+                    Paragraph::new(text)
+                        .wrap(Wrap { trim: false })
+                        .block(Block::default().title(title).borders(Borders::ALL)),
+                    area,
+                );
+            }
+            let hints: Vec<String> = CONFLICT_KEYS
+                .iter()
+                .map(|(key, action)| format!("{key}: {action}"))
+                .collect();
+            f.render_widget(
+                Paragraph::new(hints.join("  ")).style(Style::default().fg(Color::DarkGray)),
+                chunks[2],
+            );
+        })?;
+        if event::poll(Duration::from_millis(250))?
+            && let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+        {
+            match key.code {
+                KeyCode::Char('o') => choices.push(Side::Ours),
+                KeyCode::Char('t') => choices.push(Side::Theirs),
+                KeyCode::Char('b') => choices.push(Side::Both),
+                KeyCode::Char('k') | KeyCode::Backspace => {
+                    choices.pop();
+                }
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(None);
+                }
+                KeyCode::Esc | KeyCode::Char('q') => return Ok(None),
+                _ => {}
+            }
+        }
+    }
+    write_atomically(path, &resolve_regions(regions, &choices))
+        .map_err(|e| format!("Cannot write {path}: {e}"))?;
+    Ok(Some(conflicts.len()))
+}
+
+/// The paths `!` keeps from reverts, as typed, for messages.
+fn kept_list(kept_paths: &[(String, String)]) -> String {
+    kept_paths
+        .iter()
+        .map(|(_, path)| path.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct Task {
-    description: String,
-    status: Status,
+/// Writes `tasks` to the tasks file, or back into the `--markdown` file.
+fn save_tasks(tasks: &[Task], config: &Config) -> io::Result<()> {
+    let content = match &config.document {
+        Some(document) => document.render(tasks),
+        None => render_tasks(tasks, &config.file_format()),
+    };
+    write_tasks_file(config.tasks_file(), &content, config.backups)
 }
 
-impl Task {
-    fn new(description: String) -> Option<Self> {
-        if description.trim().is_empty() {
-            None
-        } else {
-            Some(Task {
-                description,
-                status: Status::Pending,
+/// Reads the tasks file, or the checkboxes of the `--markdown` file, whose
+/// other lines are kept in `config.document` for saving.
+fn read_tasks(config: &mut Config) -> Result<(Option<Frontmatter>, Vec<Task>), String> {
+    if !config.markdown {
+        return load_tasks(config.tasks_file());
+    }
+    let (document, tasks) = load_document(config.tasks_file())?;
+    config.document = Some(document);
+    Ok((None, tasks))
+}
+
+/// Makes sure `path` can hold the task list before anything is loaded or
+/// written: it must be a regular file (symlinks are resolved), writable unless
+/// `writable` is false as for `--readonly`, or not exist yet inside an
+/// existing directory.
+fn check_tasks_path(path: &Path, writable: bool) -> Result<(), String> {
+    let shown = path.display();
+    let target = match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_symlink() => match fs::canonicalize(path) {
+            Ok(target) => target,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(format!("Cannot resolve the symlink {shown}: {e}")),
+        },
+        Ok(_) => path.to_path_buf(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let parent = path.parent().filter(|p| !p.as_os_str().is_empty());
+            return match parent {
+                Some(dir) if !dir.is_dir() => Err(format!(
+                    "Cannot create {shown}: {} is not a directory",
+                    dir.display()
+                )),
+                _ => Ok(()),
+            };
+        }
+        Err(e) => return Err(format!("Cannot access {shown}: {e}")),
+    };
+    let meta = fs::metadata(&target).map_err(|e| format!("Cannot access {shown}: {e}"))?;
+    if !meta.is_file() {
+        return Err(format!("{shown} is not a regular file"));
+    }
+    if writable && meta.permissions().readonly() {
+        return Err(format!("{shown} is not writable"));
+    }
+    Ok(())
+}
+
+/// The command `t` runs for the task with `id` and the directory it runs it
+/// in: the task's own `test:` and `cwd:` where set, otherwise the global
+/// command where the app runs.
+fn task_test(tasks: &[Task], id: Option<u64>, global: &str) -> (String, Option<PathBuf>) {
+    let task = id.and_then(|id| tasks.iter().find(|t| t.id == id));
+    let command = task
+        .and_then(|t| t.test_command.clone())
+        .unwrap_or_else(|| global.to_string());
+    (
+        command,
+        task.and_then(|t| t.cwd.as_ref()).map(PathBuf::from),
+    )
+}
+
+/// Id of the task a TCR cycle started now commits for: the current task while
+/// it exists, otherwise (and always in batch mode) the selected one.
+fn tcr_target(
+    tasks: &[Task],
+    current_task: Option<u64>,
+    selected: usize,
+    batch_mode: bool,
+) -> Option<u64> {
+    current_task
+        .filter(|&id| !batch_mode && tasks.iter().any(|t| t.id == id))
+        .or(tasks.get(selected).map(|t| t.id))
+}
+
+/// Story points per status, e.g. `21 pts pending, 8 pts done`, leaving out
+/// statuses without any; empty when no task has points.
+fn points_summary(tasks: &[Task]) -> String {
+    Status::ALL
+        .iter()
+        .filter_map(|status| {
+            let points: Vec<u32> = tasks
+                .iter()
+                .filter(|t| &t.status == status)
+                .filter_map(|t| t.points)
+                .collect();
+            (!points.is_empty()).then(|| {
+                format!(
+                    "{} pts {}",
+                    points.iter().sum::<u32>(),
+                    format!("{status:?}").to_lowercase()
+                )
             })
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Moves pinned tasks, with their subtasks, to the top of `visible`, keeping
+/// their order among themselves.
+fn pin_to_top(tasks: &[Task], visible: &mut [usize]) {
+    let mut pinned = vec![false; tasks.len()];
+    // Depth of the pinned task whose subtree the walk is in.
+    let mut pinned_depth: Option<usize> = None;
+    for (i, task) in tasks.iter().enumerate() {
+        if pinned_depth.is_some_and(|depth| task.depth <= depth) {
+            pinned_depth = None;
+        }
+        if pinned_depth.is_none() && task.pinned {
+            pinned_depth = Some(task.depth);
         }
+        pinned[i] = pinned_depth.is_some();
     }
+    visible.sort_by_key(|&i| !pinned[i]);
 }
-const TASKS_FILE: &str = "tasks.md";
 
-fn main() -> Result<(), Box<dyn Error>> {
-    enable_raw_mode()?;
-    let mut stdout = stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
+/// Swaps the task at `index` (with its subtasks) and its previous or next
+/// sibling, returning where it ended up, or `None` if it has no such sibling.
+fn move_task(tasks: &mut [Task], index: usize, up: bool) -> Option<usize> {
+    let depth = tasks[index].depth;
+    let own = subtree(tasks, index);
+    if up {
+        let sibling = tasks[..index]
+            .iter()
+            .rposition(|task| task.depth <= depth)
+            .filter(|&i| tasks[i].depth == depth)?;
+        tasks[sibling..own.end].rotate_right(own.len());
+        Some(sibling)
+    } else {
+        let sibling = own.end;
+        if tasks.get(sibling).is_none_or(|task| task.depth != depth) {
+            return None;
+        }
+        let next = subtree(tasks, sibling);
+        tasks[index..next.end].rotate_left(own.len());
+        Some(index + next.len())
+    }
+}
 
-    let res = run_app(&mut terminal);
+/// Puts a task from the trash back with its subtasks: under the task that was
+/// above it if that is still there, else where it stood. Returns its index.
+fn restore_deleted(tasks: &mut Vec<Task>, deleted: Deleted) -> usize {
+    let depth = deleted.tasks[0].depth;
+    let at = match deleted
+        .after
+        .and_then(|id| tasks.iter().position(|t| t.id == id))
+    {
+        // A subtask of the task above goes right under it; anything else after
+        // that task's own subtasks, which may have been added since.
+        Some(above) if depth > tasks[above].depth => above + 1,
+        Some(above) => subtree(tasks, above).end,
+        None => deleted.index.min(tasks.len()),
+    };
+    let mut restored = deleted.tasks;
+    for task in &mut restored {
+        if tasks.iter().any(|t| t.id == task.id) {
+            task.id = next_id(tasks);
+        }
+    }
+    tasks.splice(at..at, restored);
+    fix_nesting(tasks);
+    at
+}
 
-    disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableMouseCapture
-    )?;
-    terminal.show_cursor()?;
+/// Deleted tasks from `.trash.md`, most recently deleted first. Each starts
+/// with a `<!-- index:N after:ID -->` line saying where it stood, followed by
+/// the task and its subtasks.
+fn load_trash() -> VecDeque<Deleted> {
+    let mut trash: VecDeque<Deleted> = VecDeque::new();
+    // Whether the last entry began with a position line, so that every task
+    // up to the next one belongs to it.
+    let mut placed = false;
+    for line in fs::read_to_string(TRASH_FILE).unwrap_or_default().lines() {
+        if let Some(position) = line
+            .strip_prefix("<!--")
+            .and_then(|rest| rest.strip_suffix("-->"))
+        {
+            let mut deleted = Deleted {
+                tasks: Vec::new(),
+                index: usize::MAX,
+                after: None,
+            };
+            for word in position.split_whitespace() {
+                if let Some(index) = word.strip_prefix("index:") {
+                    deleted.index = index.parse().unwrap_or(usize::MAX);
+                } else if let Some(id) = word.strip_prefix("after:") {
+                    deleted.after = id.parse().ok();
+                }
+            }
+            trash.push_back(deleted);
+            placed = true;
+            continue;
+        }
+        let Some(task) = parse_task_line(line) else {
+            continue;
+        };
+        match trash.back_mut() {
+            Some(deleted) if placed || task.depth > 0 => deleted.tasks.push(task),
+            // Written before positions were kept: it goes back at the end.
+            _ => trash.push_back(Deleted {
+                tasks: vec![task],
+                index: usize::MAX,
+                after: None,
+            }),
+        }
+    }
+    trash.retain(|deleted| !deleted.tasks.is_empty());
+    trash
+}
+
+fn save_trash(trash: &VecDeque<Deleted>) -> io::Result<()> {
+    let content: String = trash
+        .iter()
+        .map(|deleted| {
+            let after = deleted
+                .after
+                .map(|id| format!(" after:{id}"))
+                .unwrap_or_default();
+            let mut entry = format!("<!-- index:{}{after} -->\n", deleted.index);
+            for task in &deleted.tasks {
+                entry.push_str(&format!(
+                    "{}- [{}] {}\n",
+                    INDENT.repeat(task.depth),
+                    task.status.marker(),
+                    task.to_file_line()
+                ));
+            }
+            entry
+        })
+        .collect();
+    fs::write(TRASH_FILE, content)
+        .map_err(|e| io::Error::new(e.kind(), format!("{TRASH_FILE}: {e}")))
+}
 
-    if let Err(err) = res {
-        println!("Error: {:?}", err);
+/// What `t` will run, as shown in the list title.
+fn test_command_label(command: &str) -> String {
+    let command = command.trim();
+    if command.is_empty() {
+        return String::from("(no test command — press T to set)");
     }
+    if command.chars().count() > TEST_COMMAND_WIDTH {
+        let cut: String = command.chars().take(TEST_COMMAND_WIDTH - 1).collect();
+        format!("test: {cut}…")
+    } else {
+        format!("test: {command}")
+    }
+}
 
-    Ok(())
+/// The other tasks files next to `tasks_file` (Markdown files with checkbox
+/// lines), as candidates for `m`.
+fn tasks_files_nearby(tasks_file: &str) -> Vec<String> {
+    let path = Path::new(tasks_file);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut files: Vec<String> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "md"))
+        .filter(|p| p.file_name() != path.file_name() && p.file_name() != Some(TRASH_FILE.as_ref()))
+        .filter(|p| holds_tasks(p))
+        .map(|p| p.strip_prefix(".").unwrap_or(&p).display().to_string())
+        .collect();
+    files.sort();
+    files
 }
 
-fn run_app(
-    terminal: &mut Terminal<CrosstermBackend<std::io::Stdout>>,
-) -> Result<(), Box<dyn Error>> {
-    let mut tasks = load_tasks();
-    let mut selected = 0;
-    let mut mode = "view"; // or "input" or "edit" or "test"
-    let mut input = String::new();
-    let mut test_command = String::from(" ");
+/// Whether the file at `path` is missing, empty or has checkbox lines.
+fn holds_tasks(path: &Path) -> bool {
+    fs::read_to_string(path).map_or(true, |content| {
+        content.trim().is_empty() || content.lines().any(|line| parse_task_line(line).is_some())
+    })
+}
 
-    loop {
-        terminal.draw(|f| {
-            let chunks = Layout::default()
-                .direction(Direction::Vertical)
-                .margin(1)
-                .constraints([
-                    Constraint::Min(3),
-                    Constraint::Length(3),
-                ])
-                .split(f.size());
+/// Moves the task at `index` with its subtasks to the end of the tasks file
+/// at `path`, metadata and all. Ids taken in that file are replaced. Returns
+/// how many tasks moved.
+fn move_to_file(
+    tasks: &mut Vec<Task>,
+    index: usize,
+    path: &str,
+    config: &Config,
+) -> Result<usize, String> {
+    if std::path::absolute(path).ok() == std::path::absolute(config.tasks_file()).ok() {
+        return Err("⚠️ That is the file the task is in.".to_string());
+    }
+    check_tasks_path(Path::new(path), !config.readonly)?;
+    // Saving rewrites the whole file, which would lose the text of any other document.
+    if !holds_tasks(Path::new(path)) {
+        return Err(format!(
+            "⚠️ {path} is not a tasks file (it has no checkboxes)."
+        ));
+    }
+    let (frontmatter, mut target) = load_tasks(path)?;
+    let moved: Vec<Task> = tasks.drain(subtree(tasks, index)).collect();
+    let count = moved.len();
+    let root_depth = moved[0].depth;
+    let mut next = next_id(&target);
+    for mut task in moved {
+        task.depth -= root_depth;
+        if target.iter().any(|t| t.id == task.id) {
+            task.id = next;
+            next += 1;
+        }
+        target.push(task);
+    }
+    let target_config = Config {
+        tasks_file: path.to_string(),
+        open_file: None,
+        markdown: false,
+        document: None,
+        frontmatter,
+        ..config.clone()
+    };
+    save_tasks(&target, &target_config).map_err(|e| format!("⚠️ Could not save {e}"))?;
+    Ok(count)
+}
 
-            let task_items: Vec<ListItem> = tasks.iter().enumerate().map(|(i, task)| {
-                let prefix = match task.status {
-                    Status::Done => "[done]",
-                    Status::Working => "[working]",
-                    Status::Pending => "[ ]",
-                };
-                let line = format!("{} {}", prefix, task.description);
-                if i == selected {
-                    ListItem::new(Spans::from(line)).style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
-                } else {
-                    ListItem::new(Spans::from(line))
-                }
-            }).collect();
+/// A line of the list grouped by tag (`G`): the heading of a tag's group,
+/// `None` for the untagged tasks, with its size, or one of its tasks.
+#[derive(Debug, Clone, PartialEq)]
+enum GroupRow {
+    Header(Option<String>, usize),
+    Task(usize),
+}
 
-            let tasks_list = List::new(task_items)
-                .block(Block::default().title("Tasks (Enter: toggle, a: add, e: edit, d: delete, T: set test, t: test+commit, E: export, q: quit)").borders(Borders::ALL));
+/// The `visible` tasks under a heading per tag, sorted by tag with the untagged
+/// ones last. A task with several tags is listed in each of their groups;
+/// `collapsed` groups keep only their heading.
+fn group_rows(
+    tasks: &[Task],
+    visible: &[usize],
+    collapsed: &HashSet<Option<String>>,
+) -> Vec<GroupRow> {
+    let mut tagged: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    let mut untagged = Vec::new();
+    for &i in visible {
+        let mut tags: Vec<&str> = tasks[i].tags().collect();
+        tags.sort_unstable();
+        tags.dedup();
+        if tags.is_empty() {
+            untagged.push(i);
+        }
+        for tag in tags {
+            tagged.entry(tag).or_default().push(i);
+        }
+    }
+    let groups = tagged
+        .into_iter()
+        .map(|(tag, members)| (Some(tag.to_string()), members))
+        .chain((!untagged.is_empty()).then_some((None, untagged)));
+    let mut rows = Vec::new();
+    for (tag, members) in groups {
+        let folded = collapsed.contains(&tag);
+        rows.push(GroupRow::Header(tag, members.len()));
+        if !folded {
+            rows.extend(members.into_iter().map(GroupRow::Task));
+        }
+    }
+    rows
+}
 
-            f.render_widget(tasks_list, chunks[0]);
+/// Keys that do something with the cursor on a group heading rather than a
+/// task: moving, folding and those that do not act on the selected task.
+fn header_key(code: KeyCode) -> bool {
+    match code {
+        KeyCode::Up | KeyCode::Down | KeyCode::Enter | KeyCode::Esc => true,
+        KeyCode::Char(c) => " jkGqa/?HsvOrRLX%TIE".contains(c),
+        _ => false,
+    }
+}
 
-            if mode == "input" || mode == "edit" || mode == "test" {
-                let title = match mode {
-                    "input" => "Enter task description",
-                    "edit" => "Edit task description",
-                    "test" => "Enter test command (used by 't')",
-                    _ => unreachable!(),
-                };
-                let input_widget = Paragraph::new(input.as_ref())
-                    .block(Block::default().title(title).borders(Borders::ALL))
-                    .style(Style::default().fg(Color::Green));
-                f.render_widget(input_widget, chunks[1]);
-            }
-        })?;
+/// Every tag used in the task list, sorted, each spelling listed once.
+fn all_tags(tasks: &[Task]) -> Vec<String> {
+    let tags: std::collections::BTreeSet<&str> = tasks.iter().flat_map(Task::tags).collect();
+    tags.into_iter().map(str::to_string).collect()
+}
 
-        if event::poll(Duration::from_millis(100))? {
-            if let Event::Key(key) = event::read()? {
-                match mode {
-                    "view" => match key.code {
-                        KeyCode::Char('q') => break,
-                        KeyCode::Char('j') | KeyCode::Down => {
-                            if selected < tasks.len().saturating_sub(1) {
-                                selected += 1;
-                            }
-                        }
-                        KeyCode::Char('k') | KeyCode::Up => {
-                            if selected > 0 {
-                                selected -= 1;
-                            }
-                        }
-                        KeyCode::Char('d') => {
-                            if !tasks.is_empty() {
-                                tasks.remove(selected);
-                                if selected > 0 {
-                                    selected -= 1;
-                                }
-                                save_tasks(&tasks);
-                            }
-                        }
-                        KeyCode::Char('a') => {
-                            input.clear();
-                            mode = "input";
-                        }
-                        KeyCode::Char('e') => {
-                            if let Some(task) = tasks.get(selected) {
-                                input = task.description.clone();
-                                mode = "edit";
-                            }
-                        }
-                        KeyCode::Char('T') => {
-                            input = test_command.clone();
-                            mode = "test";
-                        }
-                        KeyCode::Char('t') => {
-                            disable_raw_mode()?;
-                            execute!(
-                                terminal.backend_mut(),
-                                LeaveAlternateScreen,
-                                DisableMouseCapture
-                            )?;
-                            if run_test_command(&test_command) {
-                                save_tasks(&tasks);
-                                if let Some(task) = tasks.get(selected) {
-                                    let message =
-                                        format!("TCR: completed task \"{}\"", task.description);
-                                    commit_tasks(&message)
-                                        .unwrap_or_else(|e| eprintln!("Commit failed: {e}"));
-                                }
-                            } else {
-                                println!("Tests failed, not committing.");
-                                let _ = Command::new("git").args(["restore", "."]).status();
-                            }
-                            println!("Press Enter to return to UI...");
-                            let _ = io::stdin().read_line(&mut String::new());
-                            enable_raw_mode()?;
-                            execute!(stdout(), EnterAlternateScreen, EnableMouseCapture)?;
-                            let backend = CrosstermBackend::new(stdout());
-                            *terminal = Terminal::new(backend)?;
-                        }
-                        KeyCode::Enter => {
-                            if let Some(task) = tasks.get_mut(selected) {
-                                task.status = match task.status {
-                                    Status::Pending => Status::Done,
-                                    Status::Done => Status::Working,
-                                    Status::Working => Status::Pending,
-                                };
-                                save_tasks(&tasks);
-                            }
-                        }
-                        KeyCode::Char('E') => {
-                            export_to_json(&tasks);
-                        }
-                        _ => {}
-                    },
-                    "input" => match key.code {
-                        KeyCode::Enter => {
-                            if let Some(task) = Task::new(input.drain(..).collect()) {
-                                tasks.push(task);
-                                save_tasks(&tasks);
-                            } else {
-                                println!("⚠️ Task description cannot be empty.");
-                            }
-                        }
-                        KeyCode::Esc => mode = "view",
-                        KeyCode::Char(c) => input.push(c),
-                        KeyCode::Backspace => {
-                            input.pop();
-                        }
-                        _ => {}
-                    },
-                    "edit" => match key.code {
-                        KeyCode::Enter => {
-                            if let Some(task) = tasks.get_mut(selected) {
-                                if let Some(updated) = Task::new(input.drain(..).collect()) {
-                                    *task = updated;
-                                    save_tasks(&tasks);
-                                } else {
-                                    println!("⚠️ Updated description cannot be empty.");
-                                }
-                            }
-                        }
-                        KeyCode::Esc => mode = "view",
-                        KeyCode::Char(c) => input.push(c),
-                        KeyCode::Backspace => {
-                            input.pop();
-                        }
-                        _ => {}
-                    },
-                    "test" => match key.code {
-                        KeyCode::Enter => {
-                            test_command = input.drain(..).collect();
-                            mode = "view";
-                        }
-                        KeyCode::Esc => mode = "view",
-                        KeyCode::Char(c) => input.push(c),
-                        KeyCode::Backspace => {
-                            input.pop();
-                        }
-                        _ => {}
-                    },
-                    _ => {}
+/// One line per task added, removed, edited or moved to another status since
+/// `snapshot` was taken, matched by id.
+fn changes_since(snapshot: &[TaskSnapshot], tasks: &[Task]) -> Vec<String> {
+    let mut changes = Vec::new();
+    for task in tasks {
+        match snapshot.iter().find(|old| old.id == task.id) {
+            None => changes.push(format!("+ added: {}", task.description)),
+            Some(old) => {
+                if old.description != task.description {
+                    changes.push(format!(
+                        "~ edited: {} -> {}",
+                        old.description, task.description
+                    ));
+                }
+                if old.status != task.status {
+                    changes.push(format!(
+                        "~ {}: {:?} -> {:?}",
+                        task.description, old.status, task.status
+                    ));
                 }
             }
         }
     }
+    for old in snapshot {
+        if !tasks.iter().any(|task| task.id == old.id) {
+            changes.push(format!("- removed: {}", old.description));
+        }
+    }
+    changes
+}
 
-    Ok(())
+/// Where the selection lands in `reloaded`: on the same task if it survived,
+/// otherwise on its nearest surviving neighbour in `old`, looking below first.
+fn surviving_selection(old: &[Task], selected: usize, reloaded: &[Task]) -> usize {
+    let position = |index: usize| {
+        old.get(index)
+            .and_then(|task| reloaded.iter().position(|t| t.id == task.id))
+    };
+    (0..old.len())
+        .find_map(|distance| {
+            position(selected + distance)
+                .or_else(|| selected.checked_sub(distance).and_then(position))
+        })
+        .unwrap_or(0)
+}
+
+/// The first Working task after `from`, wrapping around the list.
+fn next_working(tasks: &[Task], from: usize) -> Option<usize> {
+    (1..=tasks.len())
+        .map(|offset| (from + offset) % tasks.len())
+        .find(|&i| tasks[i].status == Status::Working)
 }
 
-fn load_tasks() -> Vec<Task> {
-    let content = fs::read_to_string(TASKS_FILE).unwrap_or_default();
-    content
-        .lines()
-        .filter(|line| line.trim().starts_with("- ["))
-        .map(|line| {
-            let status = if line.contains("- [x]") {
-                Status::Done
-            } else if line.contains("- [~]") {
-                Status::Working
+/// The next (or previous) Working task after `selected` in display order,
+/// wrapping around. `visible` lists task indices as shown on screen.
+fn jump_working(
+    tasks: &[Task],
+    visible: &[usize],
+    selected: usize,
+    forward: bool,
+) -> Option<usize> {
+    let len = visible.len();
+    let pos = visible
+        .iter()
+        .position(|&i| i == selected)
+        .unwrap_or(len - 1);
+    (1..=len)
+        .map(|offset| {
+            if forward {
+                (pos + offset) % len
             } else {
-                Status::Pending
-            };
-            let desc = line[5..].trim().to_string();
-            Task {
-                description: desc,
-                status,
+                (pos + len - offset % len) % len
             }
         })
-        .collect()
+        .map(|p| visible[p])
+        .find(|&i| tasks[i].status == Status::Working)
 }
 
-fn save_tasks(tasks: &[Task]) {
-    let mut content = String::from("# 📋 Task List\n\n");
+/// Drops tasks whose description, metadata and status exactly repeat an
+/// earlier task. Returns how many were removed.
+fn dedup_tasks(tasks: &mut Vec<Task>) -> usize {
+    let before = tasks.len();
+    let mut seen = HashSet::new();
+    tasks.retain(|task| seen.insert((task.to_line(), task.status.marker())));
+    before - tasks.len()
+}
 
-    // 🚧 Working
-    let working: Vec<_> = tasks
-        .iter()
-        .filter(|t| t.status == Status::Working)
-        .collect();
-    if !working.is_empty() {
-        content.push_str("## 🚧 Working\n");
-        for task in working {
-            content.push_str(&format!("- [~] {}\n", task.description));
-        }
-        content.push('\n');
+/// Reads tasks from a JSON export (`.json`) or from a text file where
+/// checkbox lines keep their status and every other non-empty line becomes
+/// a pending task. Imported tasks get fresh ids.
+fn import_tasks(path: &str) -> Result<Vec<Task>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("Cannot read {path}: {e}"))?;
+    let mut tasks: Vec<Task> = if path.ends_with(".json") {
+        serde_json::from_str(&content).map_err(|e| format!("Invalid JSON in {path}: {e}"))?
+    } else {
+        content
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+            // Imported text is taken as it is, however long.
+            .filter_map(|line| {
+                parse_task_line(line)
+                    .or_else(|| Task::new(line.trim().to_string(), 0).map(|(task, _)| task))
+            })
+            .collect()
+    };
+    for task in &mut tasks {
+        task.id = 0;
     }
+    fix_nesting(&mut tasks);
+    Ok(tasks)
+}
 
-    // 📋 Pending
-    let pending: Vec<_> = tasks
-        .iter()
-        .filter(|t| t.status == Status::Pending)
-        .collect();
-    if !pending.is_empty() {
-        content.push_str("## 📋 Pending\n");
-        for task in pending {
-            content.push_str(&format!("- [ ] {}\n", task.description));
+/// The first `scheme://...` URL in `text`.
+fn first_url(text: &str) -> Option<&str> {
+    URL_PATTERN.find(text).map(|m| m.as_str())
+}
+
+/// Opens `url` with `opener`, or the platform's default handler. Terminal
+/// openers are waited for; graphical ones are left running in the background.
+fn open_url(url: &str, opener: Option<&str>, in_terminal: bool) -> io::Result<()> {
+    let default = if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(windows) {
+        "explorer"
+    } else {
+        "xdg-open"
+    };
+    let mut parts = opener.unwrap_or(default).split_whitespace();
+    let program = parts.next().unwrap_or(default);
+    let mut command = Command::new(program);
+    command.args(parts).arg(url);
+    if in_terminal {
+        command.status()?;
+    } else {
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+    }
+    Ok(())
+}
+
+fn copy_to_clipboard(clipboard: &mut Option<Clipboard>, text: &str) -> Result<(), arboard::Error> {
+    let clipboard = match clipboard {
+        Some(clipboard) => clipboard,
+        None => clipboard.insert(Clipboard::new()?),
+    };
+    clipboard.set_text(text)
+}
+
+/// Leaves the alternate screen so a child process can use the terminal.
+fn suspend_tui(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+) -> Result<(), Box<dyn Error>> {
+    disable_raw_mode()?;
+    execute!(
+        terminal.backend_mut(),
+        LeaveAlternateScreen,
+        DisableMouseCapture
+    )?;
+    Ok(())
+}
+
+fn resume_tui(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>) -> Result<(), Box<dyn Error>> {
+    enable_raw_mode()?;
+    execute!(stdout(), EnterAlternateScreen, EnableMouseCapture)?;
+    let backend = CrosstermBackend::new(stdout());
+    *terminal = Terminal::new(backend)?;
+    Ok(())
+}
+
+/// `message`, followed by what `Task::new` found untidy in the typed text
+/// when `format_warnings` is on.
+fn noting(message: &str, warnings: &[&str], config: &Config) -> StatusMessage {
+    if config.format_warnings && !warnings.is_empty() {
+        StatusMessage::info(format!("{message} ⚠️ {}.", warnings.join(", ")))
+    } else {
+        StatusMessage::info(message)
+    }
+}
+
+/// The refusal shown when a typed `due:` value is not a date.
+fn due_error(text: &str) -> StatusMessage {
+    StatusMessage::error(format!(
+        "⚠️ due:{} is not a date; try 2024-07-01, today, tomorrow, mon, +3d or eow.",
+        invalid_due(text).unwrap_or_default()
+    ))
+}
+
+/// Parses a delay such as `3d`, `2w` or a bare number of days.
+fn parse_offset(value: &str) -> Option<chrono::Duration> {
+    let (count, unit) = match value.strip_suffix(['d', 'w']) {
+        Some(count) => (count, value.chars().last()?),
+        None => (value, 'd'),
+    };
+    let count: i64 = count.parse().ok().filter(|&count| count > 0)?;
+    Some(chrono::Duration::days(if unit == 'w' {
+        count * 7
+    } else {
+        count
+    }))
+}
+
+/// Reads `tasksmanager.toml`, or the defaults when there is none. A file that
+/// does not parse is an error, not the defaults.
+fn load_config() -> Result<Config, String> {
+    match fs::read_to_string(CONFIG_FILE) {
+        Ok(content) => toml::from_str(&content).map_err(|e| format!("{CONFIG_FILE}: {e}")),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+        Err(e) => Err(format!("Cannot read {CONFIG_FILE}: {e}")),
+    }
+}
+
+/// Writes the settings `keys` (`"relative_dates"`, or `"view.sort"` for one
+/// in the `[view]` table) back to `tasksmanager.toml`, unless `--readonly`
+/// keeps changes to this session. Only their lines change, so the rest of the
+/// file stays as the user wrote it.
+fn save_config(config: &Config, keys: &[&str]) -> Result<(), Box<dyn Error>> {
+    if config.readonly {
+        return Ok(());
+    }
+    if config.unreadable {
+        return Err(format!("{CONFIG_FILE} did not load, so it is left as it is").into());
+    }
+    let values = toml::Table::try_from(config)?;
+    let mut content = match fs::read_to_string(CONFIG_FILE) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    for key in keys {
+        let (table, name) = match key.split_once('.') {
+            Some((table, name)) => (Some(table), name),
+            None => (None, *key),
+        };
+        let value = match table {
+            Some(table) => values.get(table).and_then(|table| table.get(name)),
+            None => values.get(name),
+        };
+        if let Some(value) = value {
+            content = set_toml_key(&content, table, name, &value.to_string());
         }
-        content.push('\n');
     }
+    fs::write(CONFIG_FILE, content)?;
+    Ok(())
+}
 
-    // ✅ Done
-    let done: Vec<_> = tasks.iter().filter(|t| t.status == Status::Done).collect();
-    if !done.is_empty() {
-        content.push_str("## ✅ Done\n");
-        for task in done {
-            content.push_str(&format!("- [x] {}\n", task.description));
+/// `content` with `key` in `table` (`None` for the top level) set to the TOML
+/// `value`, every other line left as it was. A missing key is added after the
+/// last setting of its table, and a missing table at the end.
+fn set_toml_key(content: &str, table: Option<&str>, key: &str, value: &str) -> String {
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+    let mut current: Option<String> = None;
+    // Where a new key would go: after the table's last setting.
+    let mut end = table.is_none().then_some(0);
+    for (i, line) in lines.iter_mut().enumerate() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with('[') {
+            let name = trimmed.trim_matches(|c: char| c == '[' || c == ']' || c.is_whitespace());
+            current = Some(name.to_string());
+            if current.as_deref() == table {
+                end = Some(i + 1);
+            }
+            continue;
+        }
+        if current.as_deref() != table || trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        if trimmed
+            .strip_prefix(key)
+            .is_some_and(|rest| rest.trim_start().starts_with('='))
+        {
+            let indent = &line[..line.len() - trimmed.len()];
+            *line = format!("{indent}{key} = {value}");
+            return lines.join("\n") + "\n";
+        }
+        end = Some(i + 1);
+    }
+    match (end, table) {
+        (Some(at), _) => lines.insert(at, format!("{key} = {value}")),
+        (None, Some(table)) => {
+            if lines.last().is_some_and(|line| !line.trim().is_empty()) {
+                lines.push(String::new());
+            }
+            lines.push(format!("[{table}]"));
+            lines.push(format!("{key} = {value}"));
         }
-        content.push('\n');
+        (None, None) => unreachable!("the top level always has a place"),
+    }
+    lines.join("\n") + "\n"
+}
+
+fn load_state() -> State {
+    fs::read_to_string(STATE_FILE)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn save_state(state: &State) -> Result<(), Box<dyn Error>> {
+    fs::write(STATE_FILE, serde_json::to_string_pretty(state)?)?;
+    Ok(())
+}
+
+/// Describes a due date either as the date itself or relative to `today`.
+fn format_due(due: NaiveDate, today: NaiveDate, relative: bool) -> String {
+    if !relative {
+        return format!("due {due}");
     }
+    match (due - today).num_days() {
+        0 => "due today".to_string(),
+        1 => "due tomorrow".to_string(),
+        -1 => "1 day overdue".to_string(),
+        days if days > 1 => format!("due in {days} days"),
+        days => format!("{} days overdue", -days),
+    }
+}
+
+/// "Good morning" or the like, by `hour`, with how many tasks were finished
+/// on `last_opened`, the day the app was last started.
+fn greeting(tasks: &[Task], last_opened: NaiveDate, today: NaiveDate, hour: u32) -> String {
+    let hello = match hour {
+        0..12 => "Good morning",
+        12..18 => "Good afternoon",
+        _ => "Good evening",
+    };
+    let finished = tasks.iter().filter(|t| t.done == Some(last_opened)).count();
+    let when = if last_opened.succ_opt() == Some(today) {
+        "yesterday".to_string()
+    } else {
+        format!("on {}", last_opened.format("%A %-d %B"))
+    };
+    format!("{hello}! {finished} task(s) finished {when}.")
+}
 
-    fs::write(TASKS_FILE, content).expect("Failed to write file");
+/// Sets the tasks with `ids` to `target`, returning how many changed.
+fn set_statuses(tasks: &mut [Task], ids: &[u64], target: &Status, now: NaiveDateTime) -> usize {
+    let mut changed = 0;
+    for task in tasks
+        .iter_mut()
+        .filter(|t| ids.contains(&t.id) && t.status != *target)
+    {
+        task.set_status(target.clone(), now);
+        changed += 1;
+    }
+    changed
 }
 
-fn export_to_json(tasks: &[Task]) {
-    let json = serde_json::to_string_pretty(tasks).expect("Failed to serialize tasks");
-    fs::write("tasks.json", json).expect("Failed to write JSON file");
+/// Whether a task list key moves tasks around in the file (Tab, Shift+Tab, `J`
+/// and `K`), which only makes sense while the list shows file order.
+fn rearranges(code: KeyCode) -> bool {
+    matches!(
+        code,
+        KeyCode::Tab | KeyCode::BackTab | KeyCode::Char('J' | 'K')
+    )
 }
 
-fn run_test_command(command: &str) -> bool {
-    let parts: Vec<&str> = command.split_whitespace().collect();
-    if parts.is_empty() {
-        return false;
+/// Whether a task list key changes the tasks, runs a commit or writes the
+/// tasks file or an export, which `--readonly` refuses.
+fn changes_tasks(code: KeyCode, config: &Config) -> bool {
+    match code {
+        // Adding, editing and removing tasks, `.` repeating one of those.
+        KeyCode::Char('a' | 'N' | 'e' | 'A' | 'S' | 'y' | 'd' | 'm' | 'I' | 'X' | '.') => true,
+        // Status, flags and what is stored with a task.
+        KeyCode::Char(' ' | '*' | 'p' | 'P' | '#' | 'M' | 'x' | 'F' | 'T' | 'W') => true,
+        KeyCode::Char('J' | 'K') | KeyCode::Tab | KeyCode::BackTab => true,
+        // TCR commits (`t`, `B`), the list committed or saved (`c`, `w`) and
+        // the export written (`E`).
+        KeyCode::Char('t' | 'B' | 'c' | 'w' | 'E') => true,
+        KeyCode::Enter => config.enter_action != "detail",
+        _ => false,
     }
-    Command::new(parts[0])
-        .args(&parts[1..])
-        .status()
-        .map(|status| status.success())
-        .unwrap_or(false)
 }
 
-fn commit_tasks(message: &str) -> Result<(), String> {
-    let add = Command::new("git")
-        .args(["add", "-A"])
-        .status()
-        .map_err(|e| e.to_string())?;
-    if !add.success() {
-        return Err("git add failed".to_string());
+/// Modes that show the input box and type into `InputBuffer`.
+fn is_input_mode(mode: &str) -> bool {
+    matches!(
+        mode,
+        "input"
+            | "template_input"
+            | "points_input"
+            | "done_note"
+            | "tag_input"
+            | "move_path"
+            | "task_cwd"
+            | "keep_path"
+            | "task_test"
+            | "edit"
+            | "test"
+            | "export"
+            | "split"
+            | "filter"
+            | "import"
+            | "import_confirm"
+            | "defer"
+    )
+}
+
+/// The bindings worth showing in the footer for `mode`.
+fn mode_keys(mode: &str) -> &'static [(&'static str, &'static str)] {
+    match mode {
+        "view" => VIEW_KEYS,
+        "banner" | "changes" | "help" | "stats" | "detail" | "summary" => DISMISS_KEYS,
+        "trash" => TRASH_KEYS,
+        "tags" => TAG_KEYS,
+        "move_file" => MOVE_KEYS,
+        "tag_input" => TAG_INPUT_KEYS,
+        "templates" => TEMPLATE_KEYS,
+        "points" => POINTS_KEYS,
+        "tcr_log" => LOG_KEYS,
+        "template_input" => TEMPLATE_INPUT_KEYS,
+        "output" => OUTPUT_KEYS,
+        "commit_confirm" | "bulk_confirm" | "git_init" | "test_confirm" | "rollover" => {
+            CONFIRM_KEYS
+        }
+        "bulk_status" => BULK_KEYS,
+        "import_choice" => IMPORT_KEYS,
+        _ => INPUT_KEYS,
     }
+}
 
-    let commit = Command::new("git")
-        .args(["commit", "-m", message])
-        .status()
-        .map_err(|e| e.to_string())?;
+/// Drawn over the whole frame last in plain mode: every cell keeps its text
+/// and modifiers such as bold but loses its colors.
+struct Monochrome;
 
-    if !commit.success() {
-        return Err("git commit failed".to_string());
+impl Widget for Monochrome {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        for y in area.top()..area.bottom() {
+            for x in area.left()..area.right() {
+                let cell = buf.get_mut(x, y);
+                cell.fg = Color::Reset;
+                cell.bg = Color::Reset;
+            }
+        }
     }
+}
 
-    Ok(())
+/// Returns a rectangle centered in `area`, sized as a percentage of it.
+fn centered_rect(percent_x: u16, percent_y: u16, area: Rect) -> Rect {
+    let vertical = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Percentage((100 - percent_y) / 2),
+            Constraint::Percentage(percent_y),
+            Constraint::Percentage((100 - percent_y) / 2),
+        ])
+        .split(area);
+    Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Percentage((100 - percent_x) / 2),
+            Constraint::Percentage(percent_x),
+            Constraint::Percentage((100 - percent_x) / 2),
+        ])
+        .split(vertical[1])[1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sets_one_toml_key_and_keeps_the_rest() {
+        let content = "# my settings\ntasks_file = \"todo.json\"\n\n[view]\n# newest first\nsort = \"due\"\n\n[theme]\ndone = \"green\"\n";
+        let changed = set_toml_key(content, Some("view"), "sort", "\"name\"");
+        assert_eq!(
+            changed,
+            content.replace("sort = \"due\"", "sort = \"name\"")
+        );
+
+        let added = set_toml_key(content, Some("view"), "filter", "\"@home\"");
+        assert!(added.contains("sort = \"due\"\nfilter = \"@home\"\n\n[theme]"));
+
+        let top = set_toml_key(content, None, "relative_dates", "true");
+        assert!(
+            top.starts_with("# my settings\ntasks_file = \"todo.json\"\nrelative_dates = true\n")
+        );
+
+        let new_table = set_toml_key(
+            "tasks_file = \"todo.json\"\n",
+            Some("view"),
+            "sort",
+            "\"due\"",
+        );
+        assert_eq!(
+            new_table,
+            "tasks_file = \"todo.json\"\n\n[view]\nsort = \"due\"\n"
+        );
+        assert_eq!(
+            set_toml_key("", None, "full_output", "true"),
+            "full_output = true\n"
+        );
+    }
+
+    /// Tasks from checkbox lines, indented two spaces per level.
+    fn parse(lines: &[&str]) -> Vec<Task> {
+        lines
+            .iter()
+            .filter_map(|line| parse_task_line(line))
+            .collect()
+    }
+
+    fn outline(tasks: &[Task]) -> Vec<String> {
+        tasks
+            .iter()
+            .map(|t| format!("{}{}", INDENT.repeat(t.depth), t.description))
+            .collect()
+    }
+
+    #[test]
+    fn restores_a_deleted_subtree_under_the_task_that_was_above_it() {
+        let mut tasks = parse(&[
+            "- [ ] a id:1",
+            "  - [ ] b id:2",
+            "    - [ ] c id:3",
+            "  - [ ] d id:4",
+            "- [ ] e id:5",
+        ]);
+        let deleted = Deleted {
+            tasks: tasks.drain(1..3).collect(),
+            index: 1,
+            after: Some(1),
+        };
+        // A task added above `e` since does not take its place.
+        tasks.insert(0, parse(&["- [ ] new id:6"]).remove(0));
+        assert_eq!(restore_deleted(&mut tasks, deleted), 2);
+        assert_eq!(outline(&tasks), ["new", "a", "  b", "    c", "  d", "e"]);
+
+        // With the task above gone, it goes back where it stood.
+        let deleted = Deleted {
+            tasks: tasks.drain(2..4).collect(),
+            index: 2,
+            after: Some(9),
+        };
+        assert_eq!(restore_deleted(&mut tasks, deleted), 2);
+        assert_eq!(outline(&tasks), ["new", "a", "  b", "    c", "  d", "e"]);
+
+        let last = Deleted {
+            tasks: tasks.drain(5..).collect(),
+            index: 5,
+            after: Some(1),
+        };
+        // A sibling of `a` comes back after `a`'s subtasks.
+        assert_eq!(restore_deleted(&mut tasks, last), 5);
+        assert_eq!(outline(&tasks), ["new", "a", "  b", "    c", "  d", "e"]);
+    }
+
+    #[test]
+    fn moves_a_task_with_its_subtasks_past_its_sibling() {
+        let mut tasks = parse(&["- [ ] a", "  - [ ] a1", "- [ ] b", "  - [ ] b1", "- [ ] c"]);
+        assert_eq!(move_task(&mut tasks, 2, true), Some(0));
+        assert_eq!(outline(&tasks), ["b", "  b1", "a", "  a1", "c"]);
+        assert_eq!(move_task(&mut tasks, 2, false), Some(3));
+        assert_eq!(outline(&tasks), ["b", "  b1", "c", "a", "  a1"]);
+        // A subtask stays under its parent.
+        assert_eq!(move_task(&mut tasks, 4, true), None);
+        assert_eq!(move_task(&mut tasks, 3, false), None);
+    }
+
+    #[test]
+    fn deletes_the_word_before_the_cursor() {
+        let mut input = InputBuffer::default();
+        input.set("fix the løader  ".to_string(), true);
+        input.delete_word();
+        assert_eq!((input.text.as_str(), input.cursor), ("fix the ", 8));
+        input.delete_word();
+        assert_eq!((input.text.as_str(), input.cursor), ("fix ", 4));
+
+        input.set("fix the løader".to_string(), false);
+        input.cursor = 7;
+        input.delete_word();
+        assert_eq!((input.text.as_str(), input.cursor), ("fix  løader", 4));
+        input.cursor = 0;
+        input.delete_word();
+        assert_eq!((input.text.as_str(), input.cursor), ("fix  løader", 0));
+    }
+
+    #[test]
+    fn groups_tasks_under_each_of_their_tags() {
+        let tasks = parse(&["- [ ] a #x", "- [ ] b #y #x #x", "- [ ] c"]);
+        let header = |tag: Option<&str>, len| GroupRow::Header(tag.map(str::to_string), len);
+        assert_eq!(
+            group_rows(&tasks, &[2, 1, 0], &HashSet::new()),
+            [
+                header(Some("x"), 2),
+                GroupRow::Task(1),
+                GroupRow::Task(0),
+                header(Some("y"), 1),
+                GroupRow::Task(1),
+                header(None, 1),
+                GroupRow::Task(2),
+            ]
+        );
+        let collapsed = HashSet::from([Some("x".to_string()), None]);
+        assert_eq!(
+            group_rows(&tasks, &[0, 1, 2], &collapsed),
+            [
+                header(Some("x"), 2),
+                header(Some("y"), 1),
+                GroupRow::Task(1),
+                header(None, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_what_changed_since_the_snapshot() {
+        let before = parse(&["- [ ] a id:1", "- [ ] b id:2", "- [ ] c id:3"]);
+        let after = parse(&["- [x] a id:1", "- [ ] b, reworded id:2", "- [ ] d id:4"]);
+        assert_eq!(
+            changes_since(&TaskSnapshot::of(&before), &after),
+            [
+                "~ a: Pending -> Done",
+                "~ edited: b -> b, reworded",
+                "+ added: d",
+                "- removed: c",
+            ]
+        );
+        assert!(changes_since(&TaskSnapshot::of(&after), &after).is_empty());
+    }
+
+    #[test]
+    fn parses_deferral_offsets_in_days_and_weeks() {
+        assert_eq!(parse_offset("3d"), Some(chrono::Duration::days(3)));
+        assert_eq!(parse_offset("2w"), Some(chrono::Duration::days(14)));
+        assert_eq!(parse_offset("5"), Some(chrono::Duration::days(5)));
+        for invalid in ["", "d", "0", "-1d", "2m", "tomorrow"] {
+            assert_eq!(parse_offset(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn drops_only_exact_repeats_of_a_task() {
+        let mut tasks = parse(&[
+            "- [ ] write docs #docs",
+            "- [ ] write docs #docs",
+            "- [x] write docs #docs",
+            "- [ ] write docs",
+        ]);
+        assert_eq!(dedup_tasks(&mut tasks), 1);
+        assert_eq!(tasks.len(), 3);
+        assert_eq!(tasks[1].status, Status::Done);
+    }
+
+    #[test]
+    fn keeps_the_selection_on_the_nearest_surviving_task() {
+        let old = parse(&[
+            "- [ ] a id:1",
+            "- [ ] b id:2",
+            "- [ ] c id:3",
+            "- [ ] d id:4",
+        ]);
+        let reloaded = parse(&["- [ ] d id:4", "- [ ] b id:2", "- [ ] a id:1"]);
+        assert_eq!(surviving_selection(&old, 1, &reloaded), 1);
+        // `c` is gone: the task below it wins over the one above.
+        assert_eq!(surviving_selection(&old, 2, &reloaded), 0);
+        let reloaded = parse(&["- [ ] a id:1", "- [ ] new id:5"]);
+        assert_eq!(surviving_selection(&old, 3, &reloaded), 0);
+        assert_eq!(surviving_selection(&old, 1, &[]), 0);
+    }
+
+    #[test]
+    fn jumps_between_working_tasks_in_display_order() {
+        let tasks = parse(&["- [~] a", "- [ ] b", "- [~] c", "- [x] d"]);
+        let visible = [3, 2, 1, 0];
+        assert_eq!(jump_working(&tasks, &visible, 3, true), Some(2));
+        assert_eq!(jump_working(&tasks, &visible, 2, true), Some(0));
+        assert_eq!(jump_working(&tasks, &visible, 0, true), Some(2));
+        assert_eq!(jump_working(&tasks, &visible, 2, false), Some(0));
+        assert_eq!(jump_working(&tasks, &visible, 1, false), Some(2));
+        // A filter hiding the other Working task leaves only this one.
+        assert_eq!(jump_working(&tasks, &[0, 1], 0, true), Some(0));
+        assert_eq!(jump_working(&tasks, &[1, 3], 1, true), None);
+    }
+
+    #[test]
+    fn pins_tasks_and_their_subtasks_to_the_top() {
+        let tasks = parse(&[
+            "- [ ] a",
+            "- [ ] b pinned:yes",
+            "  - [ ] b1",
+            "- [ ] c",
+            "- [ ] d pinned:yes",
+        ]);
+        let mut visible = vec![4, 3, 2, 1, 0];
+        pin_to_top(&tasks, &mut visible);
+        assert_eq!(visible, [4, 2, 1, 3, 0]);
+        let mut visible = vec![0, 2, 3];
+        pin_to_top(&tasks, &mut visible);
+        assert_eq!(visible, [2, 0, 3]);
+    }
 }
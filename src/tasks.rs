@@ -0,0 +1,1773 @@
+//! The task list: tasks and their statuses, queries over them, and reading
+//! and writing them as the Markdown tasks file and JSON exports.
+
+use chrono::{Datelike, Days, Local, NaiveDate, NaiveDateTime, Weekday};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::{fs, io};
+use tui::style::Color;
+
+/// How `started:` timestamps are written in the tasks file.
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+/// One level of subtask nesting in the tasks file.
+pub const INDENT: &str = "  ";
+/// Set from `issue_url` and `issue_pattern` at startup. Without it `#123` is
+/// an ordinary tag.
+pub static ISSUE_TRACKER: OnceLock<IssueTracker> = OnceLock::new();
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum Status {
+    Pending,
+    Working,
+    Done,
+}
+
+impl Status {
+    /// Every status, in the order they are listed in legends.
+    pub const ALL: [Status; 3] = [Status::Pending, Status::Working, Status::Done];
+
+    /// The status Enter moves a task to.
+    pub fn next(&self) -> Status {
+        match self {
+            Status::Pending => Status::Done,
+            Status::Done => Status::Working,
+            Status::Working => Status::Pending,
+        }
+    }
+
+    /// The prefix shown before a task in the list, unless the theme sets one.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Status::Pending => "[ ]",
+            Status::Working => "[working]",
+            Status::Done => "[done]",
+        }
+    }
+
+    /// What the status stands for, as the legend and help spell it out.
+    pub fn meaning(&self) -> &'static str {
+        match self {
+            Status::Pending => "pending, not started",
+            Status::Working => "in progress",
+            Status::Done => "finished",
+        }
+    }
+
+    /// The color the status is shown in, unless a priority color takes over.
+    pub fn color(&self) -> Color {
+        match self {
+            Status::Pending => Color::White,
+            Status::Working => Color::Magenta,
+            Status::Done => Color::Green,
+        }
+    }
+
+    /// The character between the checkbox brackets in `tasks.md`.
+    pub fn marker(&self) -> char {
+        match self {
+            Status::Pending => ' ',
+            Status::Working => '~',
+            Status::Done => 'x',
+        }
+    }
+}
+
+/// Turns issue references such as `#123` into links to the tracker.
+pub struct IssueTracker {
+    base_url: String,
+    /// `issue_pattern` anchored to match whole words.
+    pattern: Regex,
+}
+
+impl IssueTracker {
+    /// A tracker linking the words that match `issue_pattern` to `base_url`.
+    pub fn new(base_url: String, issue_pattern: &str) -> Result<Self, String> {
+        Regex::new(&format!("^(?:{issue_pattern})$"))
+            .map(|pattern| IssueTracker { base_url, pattern })
+            .map_err(|e| format!("invalid issue_pattern \"{issue_pattern}\": {e}"))
+    }
+
+    /// The link for `word` if it is an issue reference: the base URL followed
+    /// by the pattern's first group, or by the whole word if it has none.
+    pub fn link(&self, word: &str) -> Option<String> {
+        let captures = self.pattern.captures(word)?;
+        let id = captures.get(1).or_else(|| captures.get(0))?.as_str();
+        Some(format!("{}{id}", self.base_url))
+    }
+}
+
+/// Whether `word` is an issue reference rather than a tag.
+fn is_issue(word: &str) -> bool {
+    ISSUE_TRACKER
+        .get()
+        .is_some_and(|tracker| tracker.pattern.is_match(word))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Task {
+    /// Stable identifier, unique within the tasks file. Zero until assigned.
+    #[serde(default)]
+    pub id: u64,
+    pub description: String,
+    pub status: Status,
+    #[serde(default)]
+    pub due: Option<NaiveDate>,
+    /// The last day the task was moved to Working.
+    #[serde(default)]
+    pub worked: Option<NaiveDate>,
+    /// The day the task was marked Done, while it stays Done.
+    #[serde(default)]
+    pub done: Option<NaiveDate>,
+    /// Minutes spent in Working, not counting the stretch currently running.
+    #[serde(default)]
+    pub actual: u64,
+    /// When the task last entered Working, while it is still Working.
+    #[serde(default)]
+    pub started: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub priority: Option<Priority>,
+    /// Story points, set with `P` or a `pts:5` token.
+    #[serde(default)]
+    pub points: Option<u32>,
+    /// Nesting level: 0 for top-level tasks, 1 for their subtasks and so on.
+    /// A task's subtasks are the tasks right after it with a greater depth.
+    #[serde(default)]
+    pub depth: usize,
+    /// Needs review or follow-up, independently of the status (`*`).
+    #[serde(default)]
+    pub review: bool,
+    /// Listed at the top whatever the sort or status (`p`).
+    #[serde(default)]
+    pub pinned: bool,
+    /// Directory `t` runs the tests in for this task (`cwd:`), relative to
+    /// where the app runs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    /// What `t` runs for this task instead of the global test command (`test:`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub test_command: Option<String>,
+    /// Free text below the task, written as indented lines under its checkbox.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+    /// Position in the list as read from the file's `ord:` token. `save_tasks`
+    /// groups tasks by status; this lets `load_tasks` put them back in order.
+    #[serde(skip)]
+    order: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    Medium,
+    High,
+}
+
+impl Priority {
+    /// Accepts the full names and their first letters, in any case.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "high" | "h" => Some(Priority::High),
+            "medium" | "med" | "m" => Some(Priority::Medium),
+            "low" | "l" => Some(Priority::Low),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Priority::High => "high",
+            Priority::Medium => "medium",
+            Priority::Low => "low",
+        }
+    }
+}
+
+impl Task {
+    /// A Pending task from typed text, with what looks untidy in the text
+    /// (`format_warnings`). A description longer than `max_len` characters
+    /// (0 for no limit) is cut and the rest becomes its first note.
+    pub fn new(description: String, max_len: usize) -> Option<(Self, Vec<&'static str>)> {
+        let mut task = Task::from_text(&description, Status::Pending);
+        if task.description.trim().is_empty() {
+            return None;
+        }
+        let mut warnings = Vec::new();
+        if description.ends_with(char::is_whitespace) {
+            warnings.push("trailing whitespace");
+        }
+        if task.description.contains("  ") {
+            warnings.push("double spaces");
+        }
+        // Tags are left out, so `#WIP` alone does not count as shouting.
+        let letters: Vec<char> = task
+            .description
+            .split_whitespace()
+            .filter(|word| !word.starts_with('#'))
+            .flat_map(str::chars)
+            .filter(|c| c.is_alphabetic())
+            .collect();
+        if letters.len() > 3 && letters.iter().all(|c| c.is_uppercase()) {
+            warnings.push("all caps");
+        }
+        task.limit_description(max_len);
+        Some((task, warnings))
+    }
+
+    /// Cuts the description to at most `max_len` characters, at a space if
+    /// there is one, and moves the rest to the front of the notes. `#tags`
+    /// in the rest stay on the line so filters still find the task.
+    fn limit_description(&mut self, max_len: usize) {
+        if max_len == 0 {
+            return;
+        }
+        let (head, rest) = split_at_width(&self.description, max_len);
+        let (tags, rest): (Vec<&str>, Vec<&str>) = rest
+            .split_whitespace()
+            .partition(|word| word.len() > 1 && word.starts_with('#'));
+        let description = std::iter::once(head)
+            .chain(tags)
+            .collect::<Vec<_>>()
+            .join(" ");
+        if !rest.is_empty() {
+            self.notes.insert(0, rest.join(" "));
+        }
+        self.description = description;
+    }
+
+    /// Builds a task from a description containing metadata tokens such as
+    /// `due:2024-07-01`. Tokens are taken out; the rest keeps its spacing.
+    /// A word escaped with a leading `\` is taken as plain text, minus the `\`.
+    fn from_text(text: &str, status: Status) -> Self {
+        let mut task = Task::blank(status);
+        let words: Vec<&str> = text
+            .trim()
+            .split(' ')
+            .filter(|word| !task.apply_token(word))
+            .map(|word| word.strip_prefix('\\').unwrap_or(word))
+            .collect();
+        task.description = words.join(" ").trim().to_string();
+        task
+    }
+
+    /// A task with no description and no metadata.
+    fn blank(status: Status) -> Self {
+        Task {
+            id: 0,
+            description: String::new(),
+            status,
+            due: None,
+            worked: None,
+            done: None,
+            actual: 0,
+            started: None,
+            priority: None,
+            points: None,
+            depth: 0,
+            review: false,
+            pinned: false,
+            cwd: None,
+            test_command: None,
+            notes: Vec::new(),
+            order: None,
+        }
+    }
+
+    fn apply_token(&mut self, word: &str) -> bool {
+        let Some((key, value)) = word.split_once(':') else {
+            return false;
+        };
+        match key {
+            "due" => fill(&mut self.due, parse_due(value)),
+            "worked" => fill(&mut self.worked, parse_date(value)),
+            "done" => fill(&mut self.done, parse_date(value)),
+            "actual" => match parse_minutes(value) {
+                Some(minutes) => {
+                    self.actual = minutes;
+                    true
+                }
+                None => false,
+            },
+            "started" => fill(
+                &mut self.started,
+                NaiveDateTime::parse_from_str(value, TIMESTAMP_FORMAT).ok(),
+            ),
+            "pri" => fill(&mut self.priority, Priority::parse(value)),
+            "pts" => fill(&mut self.points, value.parse().ok()),
+            "review" if value == "yes" => {
+                self.review = true;
+                true
+            }
+            "pinned" if value == "yes" => {
+                self.pinned = true;
+                true
+            }
+            "cwd" => fill(&mut self.cwd, decode_value(value)),
+            "test" => fill(&mut self.test_command, decode_value(value)),
+            "ord" => fill(&mut self.order, value.parse().ok()),
+            "id" => match value.parse() {
+                Ok(id) => {
+                    self.id = id;
+                    true
+                }
+                Err(_) => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// The description followed by its editable metadata tokens.
+    pub fn to_line(&self) -> String {
+        let mut line = self.escaped_description();
+        if let Some(priority) = self.priority {
+            line.push_str(&format!(" pri:{}", priority.label()));
+        }
+        if let Some(points) = self.points {
+            line.push_str(&format!(" pts:{points}"));
+        }
+        if let Some(due) = self.due {
+            line.push_str(&format!(" due:{}", due.format("%Y-%m-%d")));
+        }
+        if let Some(worked) = self.worked {
+            line.push_str(&format!(" worked:{}", worked.format("%Y-%m-%d")));
+        }
+        if let Some(done) = self.done {
+            line.push_str(&format!(" done:{}", done.format("%Y-%m-%d")));
+        }
+        if self.actual > 0 {
+            line.push_str(&format!(" actual:{}", format_minutes(self.actual)));
+        }
+        if self.review {
+            line.push_str(" review:yes");
+        }
+        if self.pinned {
+            line.push_str(" pinned:yes");
+        }
+        if let Some(cwd) = &self.cwd {
+            line.push_str(&format!(" cwd:{}", encode_value(cwd)));
+        }
+        if let Some(command) = &self.test_command {
+            line.push_str(&format!(" test:{}", encode_value(command)));
+        }
+        line
+    }
+
+    /// The description with a `\` in front of words that would otherwise be
+    /// read back as metadata tokens, such as a literal `id:3`, or that start
+    /// with a `\` themselves.
+    fn escaped_description(&self) -> String {
+        self.description
+            .split(' ')
+            .map(|word| {
+                if word.starts_with('\\') || Task::blank(Status::Pending).apply_token(word) {
+                    format!("\\{word}")
+                } else {
+                    word.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// The line as stored in the tasks file, including the internal `id:` token.
+    pub fn to_file_line(&self) -> String {
+        format!("{} id:{}", self.to_saved_line(), self.id)
+    }
+
+    /// Everything `to_file_line` stores but the id: the editable line plus a
+    /// running timer's `started:`. `--markdown` files are written with it.
+    fn to_saved_line(&self) -> String {
+        let mut line = self.to_line();
+        if let Some(started) = self.started {
+            line.push_str(&format!(" started:{}", started.format(TIMESTAMP_FORMAT)));
+        }
+        line
+    }
+
+    /// Time spent in Working so far, including a stretch that is still running.
+    pub fn tracked_minutes(&self, now: NaiveDateTime) -> u64 {
+        let running = self
+            .started
+            .map_or(0, |started| (now - started).num_minutes().max(0) as u64);
+        self.actual + running
+    }
+
+    /// The star in front of tasks flagged for review, or blank space to keep
+    /// the list aligned. A pin takes the place of the star, as both are two
+    /// columns wide; `ascii` shows them as `P` and `*`.
+    pub fn review_gutter(&self, ascii: bool) -> &'static str {
+        if self.pinned {
+            if ascii { "P " } else { "📌" }
+        } else if self.review {
+            if ascii { "* " } else { "★ " }
+        } else {
+            "  "
+        }
+    }
+
+    /// Changes the status, recording the day whenever the task enters Working
+    /// and the day it was finished while it is Done. Time spent in Working is
+    /// added to `actual` when the task leaves it.
+    pub fn set_status(&mut self, status: Status, now: NaiveDateTime) {
+        let today = now.date();
+        if status == Status::Working {
+            self.worked = Some(today);
+            if self.status != Status::Working || self.started.is_none() {
+                self.started = Some(now);
+            }
+        } else {
+            self.actual = self.tracked_minutes(now);
+            self.started = None;
+        }
+        self.done = (status == Status::Done).then_some(today);
+        self.status = status;
+    }
+
+    pub fn is_overdue(&self, today: NaiveDate) -> bool {
+        self.status != Status::Done && self.due.is_some_and(|due| due < today)
+    }
+
+    pub fn is_due(&self, today: NaiveDate) -> bool {
+        self.status != Status::Done && self.due.is_some_and(|due| due <= today)
+    }
+
+    /// Whether the daily roll-over carries the task to `today`: it is Working
+    /// but was last worked on an earlier day.
+    pub fn rolls_over(&self, today: NaiveDate) -> bool {
+        self.status == Status::Working && self.worked.is_none_or(|day| day < today)
+    }
+
+    /// Whether the daily roll-over starts a `#daily` task over: it was
+    /// finished on an earlier day.
+    pub fn resets_daily(&self, today: NaiveDate) -> bool {
+        self.status == Status::Done
+            && self.tags().any(|tag| tag == "daily")
+            && self.done.is_none_or(|day| day < today)
+    }
+
+    /// Rolls the task over to `now`'s day: a Working task counts as worked on
+    /// today and an overdue due date moves to today, a finished `#daily` task
+    /// is Pending again.
+    pub fn roll_over(&mut self, now: NaiveDateTime) {
+        let today = now.date();
+        if self.rolls_over(today) {
+            self.worked = Some(today);
+            if self.is_overdue(today) {
+                self.due = Some(today);
+            }
+        } else if self.resets_daily(today) {
+            self.set_status(Status::Pending, now);
+        }
+    }
+
+    /// `#tag` words in the description, without the leading `#`.
+    pub fn tags(&self) -> impl Iterator<Item = &str> {
+        self.description
+            .split_whitespace()
+            .filter(|word| !is_issue(word))
+            .filter_map(|word| word.strip_prefix('#'))
+            .filter(|tag| !tag.is_empty())
+    }
+
+    /// The description with its `#tags` left out.
+    pub fn untagged_description(&self) -> String {
+        self.description
+            .split_whitespace()
+            .filter(|word| word.len() < 2 || !word.starts_with('#') || is_issue(word))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// The tracker link of the first issue reference in the description.
+    pub fn issue_link(&self) -> Option<String> {
+        let tracker = ISSUE_TRACKER.get()?;
+        self.description
+            .split_whitespace()
+            .find_map(|word| tracker.link(word))
+    }
+
+    /// Copies over the tags of `other` that this task does not have yet.
+    pub fn inherit_tags(&mut self, other: &Task) {
+        let missing: Vec<String> = other
+            .tags()
+            .filter(|tag| !self.tags().any(|t| t == *tag))
+            .map(|tag| format!("#{tag}"))
+            .collect();
+        for tag in missing {
+            self.description.push(' ');
+            self.description.push_str(&tag);
+        }
+    }
+
+    /// A Pending copy with id `id` and none of the time spent on this one.
+    pub fn duplicate(&self, id: u64) -> Task {
+        Task {
+            id,
+            status: Status::Pending,
+            worked: None,
+            done: None,
+            actual: 0,
+            started: None,
+            ..self.clone()
+        }
+    }
+
+    /// Adds `#tag` to the end of the description, unless the task has it.
+    pub fn add_tag(&mut self, tag: &str) {
+        if !self.tags().any(|t| t.eq_ignore_ascii_case(tag)) {
+            self.description.push_str(&format!(" #{tag}"));
+        }
+    }
+
+    /// Takes every `#tag` word out of the description.
+    pub fn remove_tag(&mut self, tag: &str) {
+        let words: Vec<&str> = self
+            .description
+            .split(' ')
+            .filter(|word| {
+                !word
+                    .strip_prefix('#')
+                    .is_some_and(|t| t.eq_ignore_ascii_case(tag))
+            })
+            .collect();
+        self.description = words.join(" ").trim().to_string();
+    }
+}
+
+/// A stretch of a tasks file: lines both sides of a merge agree on, or a
+/// Git conflict between our side and theirs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Region {
+    Clean(Vec<String>),
+    Conflict {
+        ours: Vec<String>,
+        theirs: Vec<String>,
+    },
+}
+
+/// Which side of a conflict the resolved file keeps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Side {
+    Ours,
+    Theirs,
+    /// Ours, then theirs.
+    Both,
+}
+
+/// Splits a tasks file at its Git conflict markers. The base section of a
+/// diff3-style conflict is dropped. Returns `None` when the file has no
+/// conflict or a marker is out of place, such as a conflict left unclosed.
+pub fn conflict_regions(content: &str) -> Option<Vec<Region>> {
+    enum Part {
+        Clean,
+        Ours,
+        Base,
+        Theirs,
+    }
+    let mut regions = Vec::new();
+    let mut clean = Vec::new();
+    let (mut ours, mut theirs) = (Vec::new(), Vec::new());
+    let mut part = Part::Clean;
+    for line in content.lines() {
+        part = match part {
+            Part::Clean if line.starts_with("<<<<<<<") => {
+                regions.push(Region::Clean(std::mem::take(&mut clean)));
+                Part::Ours
+            }
+            Part::Clean if line.starts_with("=======") || line.starts_with(">>>>>>>") => {
+                return None;
+            }
+            Part::Ours | Part::Base if line.starts_with("=======") => Part::Theirs,
+            Part::Ours if line.starts_with("|||||||") => Part::Base,
+            Part::Theirs if line.starts_with(">>>>>>>") => {
+                regions.push(Region::Conflict {
+                    ours: std::mem::take(&mut ours),
+                    theirs: std::mem::take(&mut theirs),
+                });
+                Part::Clean
+            }
+            _ if ["<<<<<<<", "|||||||", "=======", ">>>>>>>"]
+                .iter()
+                .any(|marker| line.starts_with(marker)) =>
+            {
+                return None;
+            }
+            Part::Clean => {
+                clean.push(line.to_string());
+                Part::Clean
+            }
+            Part::Ours => {
+                ours.push(line.to_string());
+                Part::Ours
+            }
+            Part::Base => Part::Base,
+            Part::Theirs => {
+                theirs.push(line.to_string());
+                Part::Theirs
+            }
+        };
+    }
+    if !matches!(part, Part::Clean) || regions.is_empty() {
+        return None;
+    }
+    regions.push(Region::Clean(clean));
+    regions.retain(|region| region != &Region::Clean(Vec::new()));
+    Some(regions)
+}
+
+/// Writes `regions` back out as a file, keeping the side chosen for each
+/// conflict in turn.
+pub fn resolve_regions(regions: &[Region], choices: &[Side]) -> String {
+    let mut choices = choices.iter();
+    let mut lines: Vec<&str> = Vec::new();
+    for region in regions {
+        match region {
+            Region::Clean(clean) => lines.extend(clean.iter().map(String::as_str)),
+            Region::Conflict { ours, theirs } => {
+                let side = choices.next().copied().unwrap_or(Side::Both);
+                if side != Side::Theirs {
+                    lines.extend(ours.iter().map(String::as_str));
+                }
+                if side != Side::Ours {
+                    lines.extend(theirs.iter().map(String::as_str));
+                }
+            }
+        }
+    }
+    let mut content = lines.join("\n");
+    content.push('\n');
+    content
+}
+
+/// The `[headers]` section: headings written to the tasks file. Loading never
+/// depends on them, only on the checkbox markers.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Headers {
+    /// `"emoji"` (the default) or `"ascii"` for terminals and fonts that render emoji poorly.
+    pub preset: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub working: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pending: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub done: Option<String>,
+}
+
+impl Headers {
+    pub fn is_ascii(&self) -> bool {
+        self.preset.eq_ignore_ascii_case("ascii")
+    }
+
+    pub fn title(&self) -> &str {
+        let preset = if self.is_ascii() {
+            "Task List"
+        } else {
+            "📋 Task List"
+        };
+        self.title.as_deref().unwrap_or(preset)
+    }
+
+    pub fn section(&self, status: &Status) -> &str {
+        let (custom, emoji, ascii) = match status {
+            Status::Working => (&self.working, "🚧 Working", "Working"),
+            Status::Pending => (&self.pending, "📋 Pending", "Pending"),
+            Status::Done => (&self.done, "✅ Done", "Done"),
+        };
+        custom
+            .as_deref()
+            .unwrap_or(if self.is_ascii() { ascii } else { emoji })
+    }
+}
+
+/// A `---` fenced block at the top of the tasks file. The lines are kept as
+/// written so the block round-trips; only simple `key: value` lines are read.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Frontmatter {
+    lines: Vec<String>,
+}
+
+impl Frontmatter {
+    /// Splits a leading frontmatter block off `content`.
+    pub fn split(content: &str) -> (Option<Frontmatter>, &str) {
+        let Some(rest) = content
+            .strip_prefix("---\n")
+            .or_else(|| content.strip_prefix("---\r\n"))
+        else {
+            return (None, content);
+        };
+        let mut lines = Vec::new();
+        let mut offset = 0;
+        for line in rest.split_inclusive('\n') {
+            offset += line.len();
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line == "---" {
+                return (Some(Frontmatter { lines }), &rest[offset..]);
+            }
+            lines.push(line.to_string());
+        }
+        // No closing fence: not frontmatter after all.
+        (None, content)
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.lines.iter().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            if name.trim() != key {
+                return None;
+            }
+            let value = value.trim();
+            if value.starts_with('"') {
+                serde_json::from_str(value).ok()
+            } else if let Some(quoted) = value.strip_prefix('\'') {
+                quoted.strip_suffix('\'').map(str::to_string)
+            } else {
+                Some(value.to_string())
+            }
+            .filter(|value| !value.is_empty())
+        })
+    }
+
+    /// Replaces the value of `key`, if the block has it, leaving other lines untouched.
+    pub fn update(&mut self, key: &str, value: &str) -> bool {
+        let Some(line) = self.lines.iter_mut().find(|line| {
+            line.split_once(':')
+                .is_some_and(|(name, _)| name.trim() == key)
+        }) else {
+            return false;
+        };
+        let quoted = serde_json::to_string(value).unwrap_or_default();
+        *line = format!("{key}: {quoted}");
+        true
+    }
+}
+
+/// Reads the tasks file along with its frontmatter block, if it starts with
+/// one. Refuses files that still contain Git conflict markers, since both
+/// sides of the conflict would load as separate tasks.
+pub fn load_tasks(path: &str) -> Result<(Option<Frontmatter>, Vec<Task>), String> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("Cannot read {path}: {e}")),
+    };
+    if let Some((number, _)) = content.lines().enumerate().find(|(_, line)| {
+        ["<<<<<<<", "=======", ">>>>>>>"]
+            .iter()
+            .any(|marker| line.starts_with(marker))
+    }) {
+        return Err(format!(
+            "⚠️ {path} has a Git conflict marker on line {}; resolve the conflict first",
+            number + 1
+        ));
+    }
+    Ok(parse_tasks(&content))
+}
+
+/// Reads tasks from the content of a tasks file, as written by `render_tasks`.
+pub fn parse_tasks(content: &str) -> (Option<Frontmatter>, Vec<Task>) {
+    let (frontmatter, body) = Frontmatter::split(content);
+    let mut parsed: Vec<Task> = Vec::new();
+    for line in body.lines() {
+        if let Some(task) = parse_task_line(line) {
+            parsed.push(task);
+        } else if line.starts_with([' ', '\t'])
+            && !line.trim().is_empty()
+            && let Some(task) = parsed.last_mut()
+        {
+            let note = line.trim();
+            task.notes
+                .push(note.strip_prefix('\\').unwrap_or(note).to_string());
+        }
+    }
+    let mut tasks = restore_order(parsed);
+    fix_nesting(&mut tasks);
+    assign_ids(&mut tasks);
+    (frontmatter, tasks)
+}
+
+/// Parses a `- [ ] description` checkbox line; other lines yield `None`.
+/// The marker is the one character between the brackets and the description
+/// is everything after the space that follows them, brackets included.
+pub fn parse_task_line(line: &str) -> Option<Task> {
+    let indent = line.len() - line.trim_start().len();
+    let mut chars = line.trim_start().strip_prefix("- [")?.chars();
+    let status = match chars.next()? {
+        'x' | 'X' => Status::Done,
+        '~' => Status::Working,
+        _ => Status::Pending,
+    };
+    let text = chars.as_str().strip_prefix(']')?;
+    let mut task = Task::from_text(text.strip_prefix(' ').unwrap_or(text), status);
+    task.depth = indent / INDENT.len();
+    Some(task)
+}
+
+/// Splits `text` after at most `width` characters, at a space if there is
+/// one, trimming the spaces around the cut.
+pub fn split_at_width(text: &str, width: usize) -> (&str, &str) {
+    let Some((cut, _)) = text.char_indices().nth(width) else {
+        return (text, "");
+    };
+    let at = if text[cut..].starts_with(' ') {
+        cut
+    } else {
+        text[..cut].rfind(' ').filter(|&i| i > 0).unwrap_or(cut)
+    };
+    (text[..at].trim_end(), text[at..].trim_start())
+}
+
+/// Puts tasks back in the order of their `ord:` tokens. A task without one
+/// (e.g. added by hand) stays right after the task before it in the file.
+fn restore_order(tasks: Vec<Task>) -> Vec<Task> {
+    let mut last = 0;
+    let mut keyed: Vec<(usize, Task)> = tasks
+        .into_iter()
+        .map(|mut task| {
+            last = task.order.take().unwrap_or(last);
+            (last, task)
+        })
+        .collect();
+    keyed.sort_by_key(|(order, _)| *order);
+    keyed.into_iter().map(|(_, task)| task).collect()
+}
+
+/// Clamps depths so every subtask sits directly under a task one level up.
+pub fn fix_nesting(tasks: &mut [Task]) {
+    let mut max_depth = 0;
+    for task in tasks {
+        task.depth = task.depth.min(max_depth);
+        max_depth = task.depth + 1;
+    }
+}
+
+/// The range of `tasks` covering the task at `index` and its subtasks.
+pub fn subtree(tasks: &[Task], index: usize) -> std::ops::Range<usize> {
+    let depth = tasks[index].depth;
+    let len = tasks[index + 1..]
+        .iter()
+        .take_while(|task| task.depth > depth)
+        .count();
+    index..index + 1 + len
+}
+
+/// How many of the subtasks of the task at `index`, at any depth, are Done and
+/// how many there are. `None` for a task without subtasks.
+pub fn subtask_progress(tasks: &[Task], index: usize) -> Option<(usize, usize)> {
+    let children = &tasks[subtree(tasks, index)][1..];
+    let done = children.iter().filter(|t| t.status == Status::Done).count();
+    (!children.is_empty()).then_some((done, children.len()))
+}
+
+/// Inserts `task` as the next sibling of the task at `index`: after its
+/// subtasks and at its depth. Returns where it went.
+pub fn insert_sibling(tasks: &mut Vec<Task>, index: usize, mut task: Task) -> usize {
+    let at = subtree(tasks, index).end;
+    task.depth = tasks[index].depth;
+    tasks.insert(at, task);
+    at
+}
+
+/// The smallest id greater than every id in use.
+pub fn next_id(tasks: &[Task]) -> u64 {
+    tasks.iter().map(|t| t.id).max().unwrap_or(0) + 1
+}
+
+/// Gives fresh ids to tasks that have none (files written before ids
+/// existed) or whose id repeats an earlier task's.
+pub fn assign_ids(tasks: &mut [Task]) {
+    let mut seen = HashSet::new();
+    let mut next = next_id(tasks);
+    for task in tasks.iter_mut() {
+        if task.id == 0 || !seen.insert(task.id) {
+            task.id = next;
+            next += 1;
+        }
+    }
+}
+
+/// Writes `content` to the tasks file at `path`, first keeping `backups`
+/// numbered copies of what it held. The error names the file.
+pub fn write_tasks_file(path: &str, content: &str, backups: usize) -> io::Result<()> {
+    // A failed backup is not worth losing the edit over.
+    let _ = rotate_backups(path, backups);
+    fs::write(path, content).map_err(|e| io::Error::new(e.kind(), format!("{path}: {e}")))
+}
+
+/// A Markdown file this app did not write, such as a README with a TODO
+/// section, opened with `--markdown`. Only its checkbox items are tasks;
+/// every other line is kept as it is.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Document {
+    lines: Vec<DocumentLine>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum DocumentLine {
+    Text(String),
+    /// Where the checkbox item of the task with `id` was, with the
+    /// indentation and bullet (`-`, `*` or `+`) it was written with and the
+    /// depth its task was read at. Its notes are written from the task.
+    Item {
+        id: u64,
+        indent: String,
+        bullet: char,
+        depth: usize,
+    },
+}
+
+impl Document {
+    /// Reads the checkbox items of `content` as tasks, in file order. Lines
+    /// indented below an item are its notes, as in the tasks file.
+    pub fn parse(content: &str) -> (Document, Vec<Task>) {
+        let mut lines = Vec::new();
+        let mut tasks: Vec<Task> = Vec::new();
+        // The indentation of the item that the lines below may be notes of.
+        let mut item_indent: Option<&str> = None;
+        for line in content.lines() {
+            let body = line.trim_start();
+            let indent = &line[..line.len() - body.len()];
+            let bullet = body.chars().next().filter(|c| "-*+".contains(*c));
+            let task = bullet.and_then(|bullet| {
+                let item = body.strip_prefix(bullet)?.strip_prefix(" [")?;
+                parse_task_line(&format!("{indent}- [{item}"))
+            });
+            match (bullet, task) {
+                (Some(bullet), Some(task)) => {
+                    lines.push(DocumentLine::Item {
+                        id: 0,
+                        indent: indent.to_string(),
+                        bullet,
+                        depth: 0,
+                    });
+                    tasks.push(task);
+                    item_indent = Some(indent);
+                }
+                _ if !body.is_empty()
+                    && item_indent.is_some_and(|item| indent.len() > item.len()) =>
+                {
+                    if let Some(task) = tasks.last_mut() {
+                        task.notes
+                            .push(body.strip_prefix('\\').unwrap_or(body).to_string());
+                    }
+                }
+                _ => {
+                    lines.push(DocumentLine::Text(line.to_string()));
+                    item_indent = None;
+                }
+            }
+        }
+        fix_nesting(&mut tasks);
+        assign_ids(&mut tasks);
+        let items = lines.iter_mut().filter_map(|line| match line {
+            DocumentLine::Item { id, depth, .. } => Some((id, depth)),
+            DocumentLine::Text(_) => None,
+        });
+        for ((id, depth), task) in items.zip(&tasks) {
+            *id = task.id;
+            *depth = task.depth;
+        }
+        (Document { lines }, tasks)
+    }
+
+    /// The file with each checkbox item rewritten from the task it was read
+    /// as, matched by id, so the other lines and sections stay where they
+    /// are. Items of deleted tasks are dropped. A task the file does not have
+    /// yet follows the task before it in the list, or goes before the first
+    /// item when there is none; without items it is added at the end.
+    pub fn render(&self, tasks: &[Task]) -> String {
+        let slotted: HashSet<u64> = self
+            .lines
+            .iter()
+            .filter_map(|line| match line {
+                DocumentLine::Item { id, .. } => Some(*id),
+                DocumentLine::Text(_) => None,
+            })
+            .collect();
+        // Each task in the file, with the new tasks that follow it in the list.
+        let mut leading: Vec<&Task> = Vec::new();
+        let mut followers: Vec<(&Task, Vec<&Task>)> = Vec::new();
+        for task in tasks {
+            if slotted.contains(&task.id) {
+                followers.push((task, Vec::new()));
+            } else if let Some((_, after)) = followers.last_mut() {
+                after.push(task);
+            } else {
+                leading.push(task);
+            }
+        }
+        let write = |content: &mut String, task: &Task, indent: &str, bullet: char| {
+            content.push_str(&format!(
+                "{indent}{bullet} [{}] {}\n",
+                task.status.marker(),
+                task.to_saved_line()
+            ));
+            for note in &task.notes {
+                content.push_str(&format!("{indent}{INDENT}{}\n", escape_note(note)));
+            }
+        };
+        let mut content = String::new();
+        let mut first_item = true;
+        for line in &self.lines {
+            match line {
+                DocumentLine::Text(text) => {
+                    content.push_str(text);
+                    content.push('\n');
+                }
+                DocumentLine::Item {
+                    id,
+                    indent,
+                    bullet,
+                    depth,
+                } => {
+                    if std::mem::take(&mut first_item) {
+                        for task in &leading {
+                            write(&mut content, task, &INDENT.repeat(task.depth), *bullet);
+                        }
+                    }
+                    let Some((task, after)) = followers.iter().find(|(task, _)| task.id == *id)
+                    else {
+                        continue;
+                    };
+                    // The original indentation still fits at the depth it was read at.
+                    let indent_at = |at: usize| {
+                        if at == *depth {
+                            indent.clone()
+                        } else {
+                            INDENT.repeat(at)
+                        }
+                    };
+                    write(&mut content, task, &indent_at(task.depth), *bullet);
+                    for task in after {
+                        write(&mut content, task, &indent_at(task.depth), *bullet);
+                    }
+                }
+            }
+        }
+        if first_item && !leading.is_empty() {
+            if !content.is_empty() && !content.ends_with("\n\n") {
+                content.push('\n');
+            }
+            for task in leading {
+                write(&mut content, task, &INDENT.repeat(task.depth), '-');
+            }
+        }
+        content
+    }
+}
+
+/// A note as written under its task. Notes that read like a checkbox are
+/// escaped like descriptions, so they do not load as tasks.
+fn escape_note(note: &str) -> String {
+    if note.starts_with('\\') || parse_task_line(note).is_some() {
+        format!("\\{note}")
+    } else {
+        note.to_string()
+    }
+}
+
+/// Reads the checkbox items of a Markdown file opened with `--markdown`.
+pub fn load_document(path: &str) -> Result<(Document, Vec<Task>), String> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(Document::parse(&content)),
+        Err(e) => Err(format!("Cannot read {path}: {e}")),
+    }
+}
+
+/// The settings `render_tasks` writes the tasks file with.
+#[derive(Debug, Clone, Default)]
+pub struct FileFormat {
+    /// The headings, with the frontmatter's `project` and `theme` applied.
+    pub headers: Headers,
+    pub frontmatter: Option<Frontmatter>,
+    /// Sort each section by priority, due date and description (`sort_on_save`).
+    pub sort_on_save: bool,
+    /// Write `ord:` tokens so a reload restores the list order (`keep_order`).
+    pub keep_order: bool,
+}
+
+/// The tasks file for `tasks`: the frontmatter and title, then a section per
+/// status with subtasks under their top-level task.
+pub fn render_tasks(tasks: &[Task], format: &FileFormat) -> String {
+    let headers = &format.headers;
+    let mut content = String::new();
+    if let Some(frontmatter) = &format.frontmatter {
+        content.push_str("---\n");
+        for line in &frontmatter.lines {
+            content.push_str(line);
+            content.push('\n');
+        }
+        content.push_str("---\n\n");
+    }
+    content.push_str(&format!("# {}\n\n", headers.title()));
+
+    // Subtasks are written under their top-level task, in its section.
+    let mut roots: Vec<usize> = (0..tasks.len()).filter(|&i| tasks[i].depth == 0).collect();
+    if format.sort_on_save {
+        roots.sort_by(|&a, &b| save_order(&tasks[a], &tasks[b]));
+    }
+    for status in [Status::Working, Status::Pending, Status::Done] {
+        let group: Vec<_> = roots
+            .iter()
+            .filter(|&&i| tasks[i].status == status)
+            .flat_map(|&i| subtree(tasks, i))
+            .collect();
+        if !group.is_empty() {
+            content.push_str(&format!("## {}\n", headers.section(&status)));
+            for i in group {
+                let task = &tasks[i];
+                let order = if format.keep_order {
+                    format!(" ord:{i}")
+                } else {
+                    String::new()
+                };
+                content.push_str(&format!(
+                    "{}- [{}] {}{order}\n",
+                    INDENT.repeat(task.depth),
+                    task.status.marker(),
+                    task.to_file_line()
+                ));
+                for note in &task.notes {
+                    content.push_str(&format!(
+                        "{}{}\n",
+                        INDENT.repeat(task.depth + 1),
+                        escape_note(note)
+                    ));
+                }
+            }
+            content.push('\n');
+        }
+    }
+    content
+}
+
+/// Order of top-level tasks within a section when `sort_on_save` is on:
+/// higher priority first, then earlier due date, then description, with the
+/// id as a last resort so the result never depends on the order in memory.
+fn save_order(a: &Task, b: &Task) -> std::cmp::Ordering {
+    let key = |t: &Task| {
+        (
+            std::cmp::Reverse(t.priority),
+            t.due.is_none(),
+            t.due,
+            t.description.to_lowercase(),
+            t.id,
+        )
+    };
+    key(a).cmp(&key(b))
+}
+
+/// Drops `path.N` and anything numbered above it, shifts `path.1` ..
+/// `path.N-1` up by one and copies `path` to `path.1`. The new copy is
+/// written under a temporary name and renamed into place, so `path.1` is
+/// never half-written.
+fn rotate_backups(path: &str, count: usize) -> io::Result<()> {
+    if count == 0 || !Path::new(path).exists() {
+        return Ok(());
+    }
+    let backup = |n: usize| format!("{path}.{n}");
+    let file = Path::new(path);
+    let dir = match file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let prefix = format!(
+        "{}.",
+        file.file_name().unwrap_or_default().to_string_lossy()
+    );
+    for entry in fs::read_dir(dir)?.flatten() {
+        let name = entry.file_name();
+        let number = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(&prefix))
+            .and_then(|n| n.parse::<usize>().ok());
+        if number.is_some_and(|n| n >= count) {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    for n in (1..count).rev() {
+        if Path::new(&backup(n)).exists() {
+            fs::rename(backup(n), backup(n + 1))?;
+        }
+    }
+    let partial = format!("{path}.1.tmp");
+    fs::copy(path, &partial)?;
+    fs::rename(partial, backup(1))
+}
+
+/// A task query such as `done #release`. Every term has to match: status
+/// names (`pending`, `working`, `done`), `today` for tasks moved to Working
+/// today, `#tags`, `*` for tasks flagged for review, and plain words that are
+/// searched for in the description.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    statuses: Vec<Status>,
+    review: bool,
+    worked_on: Option<NaiveDate>,
+    tags: Vec<String>,
+    words: Vec<String>,
+}
+
+impl Filter {
+    pub fn parse(query: &str) -> Self {
+        let mut filter = Filter::default();
+        for term in query.split_whitespace() {
+            let term = term.to_lowercase();
+            match term.as_str() {
+                "pending" => filter.statuses.push(Status::Pending),
+                "working" => filter.statuses.push(Status::Working),
+                "done" => filter.statuses.push(Status::Done),
+                "today" => filter.worked_on = Some(Local::now().date_naive()),
+                "*" | "review" => filter.review = true,
+                _ => match term.strip_prefix('#') {
+                    Some(tag) if !tag.is_empty() => filter.tags.push(tag.to_string()),
+                    _ => filter.words.push(term),
+                },
+            }
+        }
+        filter
+    }
+
+    pub fn matches(&self, task: &Task) -> bool {
+        let description = task.description.to_lowercase();
+        (self.statuses.is_empty() || self.statuses.contains(&task.status))
+            && (!self.review || task.review)
+            && self.worked_on.is_none_or(|day| task.worked == Some(day))
+            && self
+                .tags
+                .iter()
+                .all(|tag| task.tags().any(|t| t.eq_ignore_ascii_case(tag)))
+            && self.words.iter().all(|word| description.contains(word))
+    }
+
+    /// File name for an export of the matching tasks, e.g. `tasks-done-tag-ui.json`.
+    pub fn export_file(&self) -> String {
+        let mut name = String::from("tasks");
+        for status in &self.statuses {
+            name.push_str(&format!("-{status:?}").to_lowercase());
+        }
+        if self.review {
+            name.push_str("-review");
+        }
+        if self.worked_on.is_some() {
+            name.push_str("-today");
+        }
+        for tag in &self.tags {
+            name.push_str(&format!("-tag-{tag}"));
+        }
+        for word in &self.words {
+            name.push('-');
+            name.push_str(word);
+        }
+        name.retain(|c| c.is_alphanumeric() || c == '-' || c == '_');
+        name.push_str(".json");
+        name
+    }
+}
+
+/// Writes the tasks matching `filter` to its export file and returns how many
+/// were written. Fields keep the order of `Task`, so exports diff cleanly, and
+/// an existing export is only replaced once the new one is complete.
+pub fn export_to_json(tasks: &[Task], filter: &Filter) -> Result<usize, String> {
+    let selected: Vec<&Task> = tasks.iter().filter(|t| filter.matches(t)).collect();
+    let mut json = serde_json::to_string_pretty(&selected).map_err(|e| e.to_string())?;
+    json.push('\n');
+    let path = filter.export_file();
+    write_atomically(&path, &json).map_err(|e| format!("cannot write {path}: {e}"))?;
+    Ok(selected.len())
+}
+
+/// Writes `content` to a temporary file next to `path` and renames it over
+/// `path`, so readers see either the old file or the new one.
+pub fn write_atomically(path: &str, content: &str) -> io::Result<()> {
+    let partial = format!("{path}.tmp");
+    fs::write(&partial, content)?;
+    fs::rename(&partial, path).inspect_err(|_| {
+        let _ = fs::remove_file(&partial);
+    })
+}
+
+/// Parses `actual:` durations such as `1h30m`, `2h` or `45m`.
+fn parse_minutes(value: &str) -> Option<u64> {
+    let (hours, rest) = match value.split_once('h') {
+        Some((hours, rest)) => (hours.parse::<u64>().ok()?, rest),
+        None => (0, value),
+    };
+    let minutes = match rest.strip_suffix('m') {
+        Some(minutes) => minutes.parse::<u64>().ok()?,
+        None if rest.is_empty() && value.ends_with('h') => 0,
+        None => return None,
+    };
+    Some(hours * 60 + minutes)
+}
+
+pub fn format_minutes(minutes: u64) -> String {
+    match (minutes / 60, minutes % 60) {
+        (0, minutes) => format!("{minutes}m"),
+        (hours, 0) => format!("{hours}h"),
+        (hours, minutes) => format!("{hours}h{minutes:02}m"),
+    }
+}
+
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()
+}
+
+/// A due date as typed: `2024-07-01`, `today`, `tomorrow`, a weekday (`mon`,
+/// `friday`) for the next one after today, `+3d` or `+2w` from today, or `eow`
+/// for the end of this week (Sunday).
+pub fn parse_due(value: &str) -> Option<NaiveDate> {
+    parse_due_from(value, Local::now().date_naive())
+}
+
+fn parse_due_from(value: &str, today: NaiveDate) -> Option<NaiveDate> {
+    if let Some(date) = parse_date(value) {
+        return Some(date);
+    }
+    let value = value.to_lowercase();
+    match value.as_str() {
+        "today" => return Some(today),
+        "tomorrow" => return today.succ_opt(),
+        "eow" => {
+            let left = 6 - today.weekday().num_days_from_monday();
+            return today.checked_add_days(Days::new(left.into()));
+        }
+        _ => {}
+    }
+    if let Some(offset) = value.strip_prefix('+') {
+        let (count, days) = match offset.strip_suffix('w') {
+            Some(count) => (count, 7),
+            None => (offset.strip_suffix('d')?, 1),
+        };
+        let count: u64 = count.parse().ok()?;
+        return today.checked_add_days(Days::new(count.checked_mul(days)?));
+    }
+    let weekday: Weekday = value.parse().ok()?;
+    let ahead = (weekday.days_since(today.weekday()) + 6) % 7 + 1;
+    today.checked_add_days(Days::new(ahead.into()))
+}
+
+/// The value of the first `due:` word in typed `text` that `parse_due` does
+/// not understand, which would otherwise end up in the description.
+pub fn invalid_due(text: &str) -> Option<&str> {
+    text.split(' ')
+        .filter_map(|word| word.strip_prefix("due:"))
+        .find(|value| parse_due(value).is_none())
+}
+
+/// A `cwd:` or `test:` value as written: tokens are single words, so spaces
+/// become `%20` (and `%` itself `%25`).
+fn encode_value(value: &str) -> String {
+    value.replace('%', "%25").replace(' ', "%20")
+}
+
+fn decode_value(value: &str) -> Option<String> {
+    (!value.is_empty()).then(|| value.replace("%20", " ").replace("%25", "%"))
+}
+
+/// Stores `value` in `slot` if there is one, reporting whether it did.
+fn fill<T>(slot: &mut Option<T>, value: Option<T>) -> bool {
+    let filled = value.is_some();
+    if filled {
+        *slot = value;
+    }
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_due_keywords_relative_to_today() {
+        // A Wednesday.
+        let today = NaiveDate::from_ymd_opt(2024, 7, 3).unwrap();
+        let day = |d| NaiveDate::from_ymd_opt(2024, 7, d);
+        assert_eq!(parse_due_from("2024-07-20", today), day(20));
+        assert_eq!(parse_due_from("today", today), day(3));
+        assert_eq!(parse_due_from("tomorrow", today), day(4));
+        assert_eq!(parse_due_from("Tomorrow", today), day(4));
+        assert_eq!(parse_due_from("+3d", today), day(6));
+        assert_eq!(parse_due_from("+2w", today), day(17));
+        assert_eq!(parse_due_from("eow", today), day(7));
+    }
+
+    #[test]
+    fn parses_weekdays_as_the_next_one_after_today() {
+        let today = NaiveDate::from_ymd_opt(2024, 7, 3).unwrap();
+        let day = |d| NaiveDate::from_ymd_opt(2024, 7, d);
+        assert_eq!(parse_due_from("thu", today), day(4));
+        assert_eq!(parse_due_from("mon", today), day(8));
+        assert_eq!(parse_due_from("friday", today), day(5));
+        assert_eq!(parse_due_from("wed", today), day(10));
+        let sunday = NaiveDate::from_ymd_opt(2024, 7, 7).unwrap();
+        assert_eq!(parse_due_from("eow", sunday), day(7));
+        assert_eq!(parse_due_from("sun", sunday), day(14));
+    }
+
+    #[test]
+    fn rejects_unknown_due_dates() {
+        let today = NaiveDate::from_ymd_opt(2024, 7, 3).unwrap();
+        for value in ["", "soon", "+d", "+3m", "3d", "2024-13-01", "+-1d"] {
+            assert_eq!(parse_due_from(value, today), None, "{value}");
+        }
+        assert_eq!(invalid_due("ship it due:soon"), Some("soon"));
+        assert_eq!(invalid_due("ship it due:tomorrow \\due:soon"), None);
+        let (task, _) = Task::new("ship it due:soon".to_string(), 0).unwrap();
+        assert_eq!(task.due, None);
+    }
+
+    #[test]
+    fn warns_about_untidy_descriptions() {
+        let warnings = |text: &str| Task::new(text.to_string(), 0).unwrap().1;
+        assert!(warnings("fix the login form #ui pri:high").is_empty());
+        assert!(warnings("bump API version").is_empty());
+        assert!(warnings("#WIP ship it").is_empty());
+        assert_eq!(warnings("ship it "), ["trailing whitespace"]);
+        assert_eq!(warnings("ship  it"), ["double spaces"]);
+        assert_eq!(warnings("SHIP IT NOW #ui"), ["all caps"]);
+    }
+
+    #[test]
+    fn parses_the_marker_between_the_brackets() {
+        let task = parse_task_line("- [x] ship it").unwrap();
+        assert_eq!(task.status, Status::Done);
+        assert_eq!(task.description, "ship it");
+        let task = parse_task_line("- [~] ship it").unwrap();
+        assert_eq!(task.status, Status::Working);
+        let task = parse_task_line("- [ ] ship it").unwrap();
+        assert_eq!(task.status, Status::Pending);
+    }
+
+    #[test]
+    fn keeps_brackets_at_the_start_of_the_description() {
+        let task = parse_task_line("- [ ] [WIP] refactor").unwrap();
+        assert_eq!(task.status, Status::Pending);
+        assert_eq!(task.description, "[WIP] refactor");
+        let task = parse_task_line("- [x] [ ] looks like a checkbox").unwrap();
+        assert_eq!(task.status, Status::Done);
+        assert_eq!(task.description, "[ ] looks like a checkbox");
+    }
+
+    #[test]
+    fn ignores_markers_inside_the_description() {
+        let task = parse_task_line("- [ ] document the - [x] syntax").unwrap();
+        assert_eq!(task.status, Status::Pending);
+        assert_eq!(task.description, "document the - [x] syntax");
+    }
+
+    #[test]
+    fn keeps_internal_spacing() {
+        let task = parse_task_line("- [ ] align  a   table").unwrap();
+        assert_eq!(task.description, "align  a   table");
+    }
+
+    #[test]
+    fn reads_the_depth_from_the_indentation() {
+        let task = parse_task_line("    - [ ] grandchild").unwrap();
+        assert_eq!(task.depth, 2);
+        assert_eq!(task.description, "grandchild");
+    }
+
+    #[test]
+    fn rejects_lines_that_are_not_checkboxes() {
+        assert!(parse_task_line("- [x").is_none());
+        assert!(parse_task_line("- [").is_none());
+        assert!(parse_task_line("- [link](https://example.com)").is_none());
+        assert!(parse_task_line("plain text").is_none());
+        assert!(parse_task_line("## Pending").is_none());
+    }
+
+    fn task(line: &str) -> Task {
+        parse_task_line(line).unwrap()
+    }
+
+    /// What a reload has to give back: status, depth, line and notes.
+    fn saved(tasks: &[Task]) -> Vec<(char, usize, String, Vec<String>)> {
+        tasks
+            .iter()
+            .map(|t| {
+                (
+                    t.status.marker(),
+                    t.depth,
+                    t.to_file_line(),
+                    t.notes.clone(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rolls_working_and_daily_tasks_over_to_today() {
+        let now = NaiveDate::from_ymd_opt(2024, 7, 3)
+            .unwrap()
+            .and_hms_opt(8, 30, 0)
+            .unwrap();
+        let today = now.date();
+        let mut tasks = vec![
+            task("- [~] draft the post due:2024-07-01 worked:2024-07-02 id:1"),
+            task("- [x] water the plants #daily done:2024-07-02 id:2"),
+            task("- [x] ship the release done:2024-07-02 id:3"),
+            task("- [~] review the PR worked:2024-07-03 id:4"),
+        ];
+        let rolling: Vec<bool> = tasks
+            .iter()
+            .map(|t| t.rolls_over(today) || t.resets_daily(today))
+            .collect();
+        assert_eq!(rolling, [true, true, false, false]);
+        for task in &mut tasks {
+            task.roll_over(now);
+        }
+        assert_eq!(tasks[0].worked, Some(today));
+        assert_eq!(tasks[0].due, Some(today));
+        assert_eq!(tasks[1].status, Status::Pending);
+        assert_eq!(tasks[1].done, None);
+        assert_eq!(tasks[2].status, Status::Done);
+    }
+
+    #[test]
+    fn inserts_duplicates_and_splits_after_the_subtasks() {
+        let mut tasks = vec![
+            task("- [x] parent id:1"),
+            task("  - [ ] child id:2"),
+            task("    - [ ] grandchild id:3"),
+            task("  - [ ] sibling id:4"),
+        ];
+        fix_nesting(&mut tasks);
+        let copy = tasks[0].duplicate(5);
+        assert_eq!(insert_sibling(&mut tasks, 0, copy), 4);
+        let split = task("- [ ] split off id:6");
+        assert_eq!(insert_sibling(&mut tasks, 1, split), 3);
+        let shape: Vec<(u64, usize)> = tasks.iter().map(|t| (t.id, t.depth)).collect();
+        assert_eq!(shape, [(1, 0), (2, 1), (3, 2), (6, 1), (4, 1), (5, 0)]);
+        assert_eq!(tasks[5].status, Status::Pending);
+        assert_eq!(subtree(&tasks, 0), 0..5);
+        tasks[2].status = Status::Done;
+        assert_eq!(subtask_progress(&tasks, 0), Some((1, 4)));
+        assert_eq!(subtask_progress(&tasks, 1), Some((1, 1)));
+        assert_eq!(subtask_progress(&tasks, 5), None);
+    }
+
+    #[test]
+    fn round_trips_metadata_notes_and_subtasks() {
+        let mut parent =
+            task("- [~] ship the release pri:high pts:3 due:2024-07-01 review:yes id:1");
+        parent.notes.push("check the changelog first".to_string());
+        let child = task("  - [ ] tag the commit #release pinned:yes id:2");
+        let tasks = vec![parent, child];
+        let (_, reloaded) = parse_tasks(&render_tasks(&tasks, &FileFormat::default()));
+        assert_eq!(saved(&reloaded), saved(&tasks));
+    }
+
+    #[test]
+    fn round_trips_the_order_with_keep_order() {
+        let tasks = vec![
+            task("- [ ] first id:1"),
+            task("- [x] second id:2"),
+            task("- [~] third id:3"),
+        ];
+        let (_, grouped) = parse_tasks(&render_tasks(&tasks, &FileFormat::default()));
+        let ids: Vec<u64> = grouped.iter().map(|t| t.id).collect();
+        assert_eq!(ids, [3, 1, 2]);
+        let format = FileFormat {
+            keep_order: true,
+            ..FileFormat::default()
+        };
+        let (_, reloaded) = parse_tasks(&render_tasks(&tasks, &format));
+        assert_eq!(saved(&reloaded), saved(&tasks));
+    }
+
+    #[test]
+    fn round_trips_the_frontmatter() {
+        let (frontmatter, _) = parse_tasks("---\nproject: \"Sprint 12\"\ntheme: ascii\n---\n");
+        assert!(frontmatter.is_some());
+        let format = FileFormat {
+            frontmatter: frontmatter.clone(),
+            ..FileFormat::default()
+        };
+        let content = render_tasks(&[task("- [ ] plan id:1")], &format);
+        let (reloaded, tasks) = parse_tasks(&content);
+        assert_eq!(reloaded, frontmatter);
+        assert_eq!(tasks[0].description, "plan");
+    }
+
+    /// Pieces descriptions are made of, picked to look like the file's own
+    /// syntax: checkboxes, section headings, metadata tokens and escapes.
+    const FRAGMENTS: [&str; 16] = [
+        "fix",
+        "the",
+        "- [x]",
+        "[ ]",
+        "✅",
+        "## 📋 Pending",
+        "due:2024-07-01",
+        "id:7",
+        "ord:2",
+        "pri:high",
+        "review:yes",
+        "\\",
+        "\\id:3",
+        "#tag",
+        "",
+        "pts:abc",
+    ];
+
+    /// Tasks with descriptions and notes stitched together from `FRAGMENTS` by
+    /// a fixed xorshift sequence, so failures reproduce.
+    fn generated_tasks(count: usize) -> Vec<Task> {
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = |bound: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % bound as u64) as usize
+        };
+        (0..count)
+            .map(|i| {
+                let words: Vec<&str> = (0..1 + next(6))
+                    .map(|_| FRAGMENTS[next(FRAGMENTS.len())])
+                    .collect();
+                let mut task = Task::blank(Status::ALL[next(3)].clone());
+                task.id = i as u64 + 1;
+                task.description = format!("{} {}", words.join(" "), i).trim().to_string();
+                if next(3) == 0 {
+                    let fragment = FRAGMENTS[next(FRAGMENTS.len())];
+                    task.notes
+                        .push(format!("{fragment} note").trim().to_string());
+                    task.notes.push("- [ ] not a task".to_string());
+                }
+                task
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trips_generated_descriptions_and_statuses() {
+        let tasks = generated_tasks(200);
+        let format = FileFormat {
+            keep_order: true,
+            ..FileFormat::default()
+        };
+        let (_, reloaded) = parse_tasks(&render_tasks(&tasks, &format));
+        assert_eq!(reloaded.len(), tasks.len());
+        for (before, after) in tasks.iter().zip(&reloaded) {
+            assert_eq!(after.description, before.description);
+            assert_eq!(after.status, before.status);
+            assert_eq!(after.notes, before.notes);
+            assert_eq!(after.id, before.id);
+        }
+    }
+
+    #[test]
+    fn round_trips_test_overrides_with_spaces() {
+        let mut task = Task::blank(Status::Pending);
+        task.description = "fix the parser".to_string();
+        task.cwd = Some("packages/my api".to_string());
+        task.test_command = Some("cargo test -p api -- 100%".to_string());
+        let line = task.to_line();
+        assert_eq!(
+            line,
+            "fix the parser cwd:packages/my%20api test:cargo%20test%20-p%20api%20--%20100%25"
+        );
+        let reloaded = Task::from_text(&line, Status::Pending);
+        assert_eq!(reloaded.description, "fix the parser");
+        assert_eq!(reloaded.cwd, task.cwd);
+        assert_eq!(reloaded.test_command, task.test_command);
+    }
+
+    #[test]
+    fn keeps_the_order_within_each_status() {
+        let tasks = generated_tasks(50);
+        let (_, reloaded) = parse_tasks(&render_tasks(&tasks, &FileFormat::default()));
+        for status in Status::ALL {
+            let ids = |tasks: &[Task]| -> Vec<u64> {
+                tasks
+                    .iter()
+                    .filter(|t| t.status == status)
+                    .map(|t| t.id)
+                    .collect()
+            };
+            assert_eq!(ids(&reloaded), ids(&tasks));
+        }
+    }
+
+    #[test]
+    fn escapes_words_that_read_as_tokens() {
+        let mut task = Task::blank(Status::Pending);
+        task.description = "bump due:2024-07-01 and \\n".to_string();
+        assert_eq!(task.to_line(), "bump \\due:2024-07-01 and \\\\n");
+        let reloaded = Task::from_text(&task.to_line(), Status::Pending);
+        assert_eq!(reloaded.description, task.description);
+        assert_eq!(reloaded.due, None);
+    }
+
+    #[test]
+    fn writes_markdown_checkboxes_back_in_place() {
+        let readme = "# Project\n\nSome text.\n\n## TODO\n* [ ] write docs\n    - [x] intro\n\nLicense: MIT\n";
+        let (document, mut tasks) = Document::parse(readme);
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(document.render(&tasks), readme);
+        tasks[0].status = Status::Working;
+        tasks.remove(1);
+        tasks.push(Task::from_text("add tests", Status::Pending));
+        assert_eq!(
+            document.render(&tasks),
+            "# Project\n\nSome text.\n\n## TODO\n* [~] write docs\n* [ ] add tests\n\nLicense: MIT\n"
+        );
+        // A running timer survives a save and reload.
+        tasks[0].started = NaiveDate::from_ymd_opt(2024, 7, 3)
+            .unwrap()
+            .and_hms_opt(9, 15, 0);
+        let (_, reloaded) = Document::parse(&document.render(&tasks));
+        assert_eq!(reloaded[0].started, tasks[0].started);
+    }
+
+    #[test]
+    fn keeps_markdown_sections_apart_when_tasks_come_and_go() {
+        let readme =
+            "## TODO\n- [ ] first\n  see the issue\n- [ ] second\n\n## Later\n- [ ] someday\n";
+        let (document, mut tasks) = Document::parse(readme);
+        assert_eq!(tasks.len(), 3);
+        assert_eq!(tasks[0].notes, ["see the issue"]);
+        assert_eq!(document.render(&tasks), readme);
+        // The note goes with its task, and Later keeps its item.
+        tasks.remove(0);
+        assert_eq!(
+            document.render(&tasks),
+            "## TODO\n- [ ] second\n\n## Later\n- [ ] someday\n"
+        );
+        // A new task follows the one it was added after, with its notes escaped.
+        let mut added = Task::from_text("third", Status::Pending);
+        added.notes.push("- [ ] not a task".to_string());
+        tasks.insert(1, added);
+        let content = document.render(&tasks);
+        assert_eq!(
+            content,
+            "## TODO\n- [ ] second\n- [ ] third\n  \\- [ ] not a task\n\n## Later\n- [ ] someday\n"
+        );
+        let (_, reloaded) = Document::parse(&content);
+        assert_eq!(reloaded.len(), 3);
+        assert_eq!(reloaded[1].notes, ["- [ ] not a task"]);
+    }
+
+    #[test]
+    fn resolves_conflicts_side_by_side() {
+        let content = "## Pending\n<<<<<<< HEAD\n- [ ] ours id:1\n||||||| base\n- [ ] base id:1\n=======\n- [ ] theirs id:1\n>>>>>>> topic\n- [ ] kept id:2\n";
+        let regions = conflict_regions(content).unwrap();
+        assert_eq!(regions.len(), 3);
+        assert_eq!(
+            resolve_regions(&regions, &[Side::Theirs]),
+            "## Pending\n- [ ] theirs id:1\n- [ ] kept id:2\n"
+        );
+        assert_eq!(
+            resolve_regions(&regions, &[Side::Both]),
+            "## Pending\n- [ ] ours id:1\n- [ ] theirs id:1\n- [ ] kept id:2\n"
+        );
+        assert_eq!(conflict_regions("<<<<<<< HEAD\n- [ ] open\n"), None);
+        assert_eq!(conflict_regions("- [ ] clean\n"), None);
+    }
+}
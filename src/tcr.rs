@@ -0,0 +1,854 @@
+//! Test-Commit-Revert: running the test command and committing or reverting
+//! through the configured version control system.
+
+use crate::tasks::split_at_width;
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+pub const TCRIGNORE_FILE: &str = ".tcrignore";
+/// Start of every commit message made by a passing TCR cycle.
+pub const TCR_PREFIX: &str = "TCR:";
+/// How many TCR commits `L` lists.
+pub const TCR_LOG_LIMIT: usize = 100;
+/// Tries of a git command that finds `index.lock` taken, e.g. by an IDE
+/// running git in the background, before giving up.
+const LOCK_ATTEMPTS: u32 = 3;
+/// Wait before the first retry after a locked index, doubled for each one after it.
+const LOCK_BACKOFF: Duration = Duration::from_millis(200);
+/// How often a test run in a multiplexer pane is checked for its exit status.
+const PANE_POLL: Duration = Duration::from_millis(100);
+
+/// Top level of the git work tree, once `repo_root` has found one.
+static REPO_ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+/// Top level of the git work tree the app runs in, so git commands behave the
+/// same from any subdirectory. `None` outside a repository; asked again each
+/// time until there is one, so a repository made by `init_repo` is picked up.
+pub fn repo_root() -> Option<&'static Path> {
+    if let Some(root) = REPO_ROOT.get() {
+        return Some(root);
+    }
+    let output = Command::new("git")
+        .args(["rev-parse", "--show-toplevel"])
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let root = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !root.is_empty())
+        .then(|| REPO_ROOT.get_or_init(|| PathBuf::from(root)).as_path())
+}
+
+/// Result of one run of the test command, with stdout and stderr interleaved
+/// line by line as they arrived.
+#[derive(Debug, Default)]
+pub struct TestRun {
+    /// `None` when the command could not be started or was killed by a signal.
+    pub exit_code: Option<i32>,
+    pub output: Vec<String>,
+}
+
+/// What a finished test run means for the TCR cycle.
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Pass,
+    /// Revert, for the reason given.
+    Fail(String),
+    /// Neither commit nor revert: the exit code is mapped to `"ignore"`.
+    Ignore(String),
+}
+
+/// A test run going on in the background while the UI stays up.
+pub struct TestJob {
+    /// Output so far, for the split view.
+    pub output: Arc<Mutex<Vec<String>>>,
+    pub handle: thread::JoinHandle<TestRun>,
+}
+
+impl TestJob {
+    pub fn spawn(command: &str, dir: Option<PathBuf>) -> Self {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let shared = Arc::clone(&output);
+        let command = command.to_string();
+        let handle =
+            thread::spawn(move || run_test_command(&command, dir.as_deref(), &shared, false));
+        TestJob { output, handle }
+    }
+
+    /// Runs the test command in a new pane of `multiplexer`, next to the app.
+    /// A wrapper script tees the output to a temporary file and writes the exit
+    /// status to another once the command is done; the job polls for it.
+    pub fn in_pane(command: &str, dir: Option<PathBuf>, multiplexer: Multiplexer) -> Self {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let shared = Arc::clone(&output);
+        let command = command.to_string();
+        let handle = thread::spawn(move || {
+            let result = run_in_pane(&command, dir.as_deref(), multiplexer, &shared);
+            let output = shared.lock().unwrap().clone();
+            match result {
+                Ok(exit_code) => TestRun { exit_code, output },
+                Err(message) => TestRun {
+                    exit_code: None,
+                    output: vec![message],
+                },
+            }
+        });
+        TestJob { output, handle }
+    }
+}
+
+/// A terminal multiplexer that can run the tests in a pane of their own.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Multiplexer {
+    Tmux,
+    WezTerm,
+}
+
+impl Multiplexer {
+    /// The multiplexer the app runs in, from the variables it sets for its panes.
+    pub fn detect() -> Option<Self> {
+        if std::env::var_os("TMUX").is_some() {
+            Some(Multiplexer::Tmux)
+        } else if std::env::var_os("WEZTERM_PANE").is_some() {
+            Some(Multiplexer::WezTerm)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Multiplexer::Tmux => "tmux",
+            Multiplexer::WezTerm => "WezTerm",
+        }
+    }
+
+    /// Opens a pane below the app running `script` with `sh`, keeping the
+    /// focus on the app, and returns the new pane's id.
+    fn split(&self, script: &str) -> Result<String, String> {
+        let mut command = match self {
+            Multiplexer::Tmux => {
+                let mut command = Command::new("tmux");
+                command.args(["split-window", "-d", "-v", "-P", "-F", "#{pane_id}"]);
+                command
+            }
+            Multiplexer::WezTerm => {
+                let mut command = Command::new("wezterm");
+                command.args(["cli", "split-pane", "--bottom", "--"]);
+                command
+            }
+        };
+        command.args(["sh", "-c", script]);
+        let failure = format!("Could not open a {} pane", self.name());
+        Ok(run_vcs(&mut command, &failure)?.trim().to_string())
+    }
+
+    /// Whether the pane `id` is still open.
+    fn pane_alive(&self, id: &str) -> bool {
+        match self {
+            Multiplexer::Tmux => Command::new("tmux")
+                .args(["display-message", "-p", "-t", id, ""])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .is_ok_and(|status| status.success()),
+            // The pane id is the third column of `wezterm cli list`.
+            Multiplexer::WezTerm => Command::new("wezterm")
+                .args(["cli", "list"])
+                .output()
+                .map(|output| {
+                    String::from_utf8_lossy(&output.stdout)
+                        .lines()
+                        .any(|line| line.split_whitespace().nth(2) == Some(id))
+                })
+                .unwrap_or(true),
+        }
+    }
+}
+
+/// Runs `command` in a new pane, in `cwd` if given and else where the app
+/// runs, and waits for its exit status, copying what it printed so far into
+/// `output` as it goes. The command is split into words as `run_test_command`
+/// does, so quotes and other shell syntax mean the same in either.
+fn run_in_pane(
+    command: &str,
+    cwd: Option<&Path>,
+    multiplexer: Multiplexer,
+    output: &Arc<Mutex<Vec<String>>>,
+) -> Result<Option<i32>, String> {
+    // The pane starts in the multiplexer's directory, not necessarily the
+    // app's, so it always changes to an absolute one. A failing `cd` is the
+    // exit status the job sees.
+    let cwd = match cwd {
+        Some(cwd) => std::path::absolute(cwd).unwrap_or_else(|_| cwd.to_path_buf()),
+        None => std::env::current_dir()
+            .map_err(|e| format!("Cannot read the current directory: {e}"))?,
+    };
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "tasksmanager-{}-{}",
+        std::process::id(),
+        RUNS.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::create_dir_all(&dir).map_err(|e| format!("Cannot create {}: {e}", dir.display()))?;
+    let log = dir.join("output");
+    let status = dir.join("status");
+    let quote = |text: &str| format!("'{}'", text.replace('\'', r"'\''"));
+    let quote_path = |path: &Path| quote(&path.display().to_string());
+    let words: Vec<String> = command.split_whitespace().map(quote).collect();
+    // The status is renamed into place after `tee` has written the last line.
+    let script = format!(
+        "(cd {cwd} && {command}; echo $? > {partial}) 2>&1 | tee {log}; mv {partial} {status}; \
+         printf '\\nPress Enter to close this pane '; read _",
+        cwd = quote_path(&cwd),
+        command = words.join(" "),
+        partial = quote_path(&dir.join("status.tmp")),
+        log = quote_path(&log),
+        status = quote_path(&status),
+    );
+    let pane = multiplexer.split(&script)?;
+    let mut polls = 0;
+    let result = loop {
+        thread::sleep(PANE_POLL);
+        let lines: Vec<String> = fs::read_to_string(&log)
+            .unwrap_or_default()
+            .lines()
+            .map(str::to_string)
+            .collect();
+        *output.lock().unwrap() = lines;
+        if let Ok(code) = fs::read_to_string(&status) {
+            break Ok(code.trim().parse().ok());
+        }
+        polls += 1;
+        // Asking the multiplexer is slower than a file check, so only now and then.
+        if polls % 10 == 0 && !multiplexer.pane_alive(&pane) {
+            break Err("The test pane was closed before the tests finished.".to_string());
+        }
+    };
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+impl TestRun {
+    /// Whether the run counts as a pass: a zero exit status, or one that
+    /// `exit_codes` maps to `"pass"`, plus the `fail_on_pattern` and
+    /// `require_pattern` checks of the output. A failure says which check
+    /// failed.
+    pub fn verdict(
+        &self,
+        exit_codes: &BTreeMap<String, String>,
+        fail_on_pattern: Option<&str>,
+        require_pattern: Option<&str>,
+    ) -> Verdict {
+        let Some(code) = self.exit_code else {
+            return Verdict::Fail("no exit status".to_string());
+        };
+        let action = exit_codes
+            .get(&code.to_string())
+            .map_or(if code == 0 { "pass" } else { "fail" }, String::as_str);
+        match action {
+            "pass" => {}
+            "ignore" => return Verdict::Ignore(format!("tests exited with code {code}")),
+            _ => return Verdict::Fail(format!("tests exited with code {code}")),
+        }
+        match self.check_patterns(fail_on_pattern, require_pattern) {
+            Ok(()) => Verdict::Pass,
+            Err(reason) => Verdict::Fail(reason),
+        }
+    }
+
+    /// Checks the output against `fail_on_pattern` and `require_pattern`.
+    fn check_patterns(
+        &self,
+        fail_on_pattern: Option<&str>,
+        require_pattern: Option<&str>,
+    ) -> Result<(), String> {
+        let output = self.output.join("\n");
+        let pattern = |source: &str| {
+            Regex::new(source).map_err(|e| format!("invalid pattern \"{source}\": {e}"))
+        };
+        if let Some(source) = fail_on_pattern
+            && pattern(source)?.is_match(&output)
+        {
+            return Err(format!("output matched fail_on_pattern \"{source}\""));
+        }
+        if let Some(source) = require_pattern
+            && !pattern(source)?.is_match(&output)
+        {
+            return Err(format!("output lacks require_pattern \"{source}\""));
+        }
+        Ok(())
+    }
+}
+
+/// A commit listed by the TCR log browser (`L`).
+pub struct TcrCommit {
+    pub hash: String,
+    /// Commit date as `YYYY-MM-DD HH:MM`.
+    pub date: String,
+    /// The subject without the TCR prefix, e.g. `completed task "Add login"`.
+    pub task: String,
+}
+
+/// Keeps the subject of a commit message within `max_subject` characters:
+/// the rest goes to the body, wrapped at the same width. 0 keeps one line.
+pub fn commit_message(text: &str, max_subject: usize) -> String {
+    if max_subject == 0 {
+        return text.to_string();
+    }
+    let (subject, mut rest) = split_at_width(text, max_subject);
+    let mut message = subject.to_string();
+    if !rest.is_empty() {
+        message.push('\n');
+    }
+    while !rest.is_empty() {
+        let (line, more) = split_at_width(rest, max_subject);
+        message.push('\n');
+        message.push_str(line);
+        rest = more;
+    }
+    message
+}
+
+/// Runs the test command, in `dir` if given, collecting its output in `output`
+/// as it arrives and, with `echo`, printing it too.
+pub fn run_test_command(
+    command: &str,
+    dir: Option<&Path>,
+    output: &Arc<Mutex<Vec<String>>>,
+    echo: bool,
+) -> TestRun {
+    let parts: Vec<&str> = command.split_whitespace().collect();
+    if parts.is_empty() {
+        return TestRun::default();
+    }
+    let mut test = Command::new(parts[0]);
+    test.args(&parts[1..])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(dir) = dir {
+        test.current_dir(dir);
+    }
+    let mut child = match test.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            let message = match dir {
+                Some(dir) => format!("Could not run {} in {}: {e}", parts[0], dir.display()),
+                None => format!("Could not run {}: {e}", parts[0]),
+            };
+            if echo {
+                println!("{message}");
+            }
+            output.lock().unwrap().push(message.clone());
+            return TestRun {
+                exit_code: None,
+                output: vec![message],
+            };
+        }
+    };
+    let mut readers = Vec::new();
+    if let Some(stdout) = child.stdout.take() {
+        readers.push(tee_lines(stdout, Arc::clone(output), echo));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        readers.push(tee_lines(stderr, Arc::clone(output), echo));
+    }
+    for reader in readers {
+        let _ = reader.join();
+    }
+    let exit_code = child.wait().ok().and_then(|status| status.code());
+    let output = output.lock().unwrap().clone();
+    TestRun { exit_code, output }
+}
+
+/// Collects each line of `stream` in `lines`, echoing it to the terminal if asked.
+fn tee_lines(
+    stream: impl Read + Send + 'static,
+    lines: Arc<Mutex<Vec<String>>>,
+    echo: bool,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines().map_while(Result::ok) {
+            if echo {
+                println!("{line}");
+            }
+            lines.lock().unwrap().push(line);
+        }
+    })
+}
+
+/// Reads the gitignore-style patterns in `.tcrignore`, skipping blank lines
+/// and `#` comments.
+pub fn load_tcrignore() -> Vec<String> {
+    fs::read_to_string(TCRIGNORE_FILE)
+        .unwrap_or_default()
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+/// Turns a gitignore-style pattern into a glob from the repository root:
+/// patterns without a slash match at any depth, a leading slash anchors the
+/// pattern to the root and a trailing slash covers a directory.
+fn ignore_glob(pattern: &str) -> String {
+    let mut glob = match pattern.strip_prefix('/') {
+        Some(anchored) => anchored.to_string(),
+        None if !pattern.trim_end_matches('/').contains('/') => format!("**/{pattern}"),
+        None => pattern.to_string(),
+    };
+    if glob.ends_with('/') {
+        glob.push_str("**");
+    }
+    glob
+}
+
+/// A Git pathspec excluding what the gitignore-style `pattern` matches.
+fn exclude_pathspec(pattern: &str) -> String {
+    format!(":(exclude,glob){}", ignore_glob(pattern))
+}
+
+/// A git command run from the repository root.
+fn git() -> Command {
+    let mut command = Command::new("git");
+    if let Some(root) = repo_root() {
+        command.current_dir(root);
+    }
+    command
+}
+
+/// Whether `path` lies inside the repository, if there is one.
+pub fn in_repo(path: &Path) -> bool {
+    let Some(root) = repo_root() else {
+        return true;
+    };
+    let Ok(path) = std::path::absolute(path) else {
+        return false;
+    };
+    // The file may not exist yet, so resolve symlinks through its directory.
+    let dir = path.parent().and_then(|dir| fs::canonicalize(dir).ok());
+    dir.is_some_and(|dir| dir.starts_with(root))
+}
+
+/// `path` as a pattern anchored at the repository root, to leave it out of a
+/// revert the way `.tcrignore` does.
+pub fn root_pattern(path: &Path) -> Option<String> {
+    let root = repo_root()?;
+    let path = std::path::absolute(path).ok()?;
+    let dir = fs::canonicalize(path.parent()?).ok()?;
+    let relative = dir.join(path.file_name()?);
+    let relative = relative.strip_prefix(root).ok()?;
+    Some(format!("/{}", relative.display()))
+}
+
+/// Unix time of the last commit, or `None` outside a repository without commits.
+pub fn last_commit_time() -> Option<i64> {
+    let output = git()
+        .args(["log", "-1", "--format=%ct"])
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+}
+
+/// Makes the current directory a git repository and commits every file in it
+/// as `Initial commit`, so TCR has something to revert to. The `ignore`
+/// patterns, along with build output, go into `.gitignore` first so the
+/// commit leaves them out.
+pub fn init_repo(ignore: &[&str]) -> Result<(), String> {
+    run_vcs(Command::new("git").arg("init"), "git init failed")?;
+    let existing = match fs::read_to_string(".gitignore") {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("Cannot read .gitignore: {e}")),
+    };
+    let missing: Vec<&str> = ["/target/"]
+        .iter()
+        .chain(ignore)
+        .copied()
+        .filter(|pattern| !existing.lines().any(|line| line.trim() == *pattern))
+        .collect();
+    if !missing.is_empty() {
+        let mut content = existing;
+        if !content.is_empty() && !content.ends_with('\n') {
+            content.push('\n');
+        }
+        for pattern in missing {
+            content.push_str(pattern);
+            content.push('\n');
+        }
+        fs::write(".gitignore", content).map_err(|e| format!("Cannot write .gitignore: {e}"))?;
+    }
+    run_vcs(Command::new("git").args(["add", "-A"]), "git add failed")?;
+    run_vcs(
+        Command::new("git").args(["commit", "--allow-empty", "-m", "Initial commit"]),
+        "git commit failed",
+    )
+    .map(|_| ())
+}
+
+/// Number of commits made by `t`, recognised by their `TCR:` subject.
+pub fn count_tcr_commits() -> Option<usize> {
+    let output = git()
+        .args([
+            "rev-list",
+            "--count",
+            &format!("--grep=^{TCR_PREFIX}"),
+            "HEAD",
+        ])
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+}
+
+/// Why the last commit must not be amended: it is not a TCR commit, or a
+/// remote branch already has it. `None` when amending it is safe.
+pub fn amend_refusal() -> Option<String> {
+    let subject = match run_vcs(git().args(["log", "-1", "--format=%s"]), "git log failed") {
+        Ok(subject) => subject,
+        Err(e) => return Some(e),
+    };
+    let subject = subject.trim();
+    if !subject.starts_with(TCR_PREFIX) {
+        return Some(format!(
+            "the last commit (\"{subject}\") is not a TCR commit"
+        ));
+    }
+    match run_vcs(
+        git().args(["branch", "-r", "--contains", "HEAD"]),
+        "git branch failed",
+    ) {
+        Ok(remotes) => remotes
+            .lines()
+            .next()
+            .map(|remote| format!("the last TCR commit is pushed to {}", remote.trim())),
+        Err(e) => Some(e),
+    }
+}
+
+/// The last `limit` TCR commits, newest first.
+pub fn tcr_log(limit: usize) -> Result<Vec<TcrCommit>, String> {
+    let stdout = run_vcs(
+        git().args([
+            "log",
+            &format!("--grep=^{TCR_PREFIX}"),
+            &format!("--max-count={limit}"),
+            "--date=format:%Y-%m-%d %H:%M",
+            "--format=%h%x09%ad%x09%s",
+        ]),
+        "git log failed",
+    )?;
+    Ok(stdout
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            let hash = fields.next()?.to_string();
+            let date = fields.next()?.to_string();
+            let subject = fields.next()?;
+            let task = subject.strip_prefix(TCR_PREFIX).unwrap_or(subject).trim();
+            Some(TcrCommit {
+                hash,
+                date,
+                task: task.to_string(),
+            })
+        })
+        .collect())
+}
+
+/// The `git show --stat` summary of the files `hash` changed.
+pub fn diff_stat(hash: &str) -> Result<Vec<String>, String> {
+    let stdout = run_vcs(
+        git().args(["show", "--stat", "--format=", hash]),
+        "git show failed",
+    )?;
+    Ok(stdout.lines().map(str::to_string).collect())
+}
+
+/// The version control system the task list and TCR cycles commit to,
+/// picked with `vcs` in the config.
+pub trait Vcs: Send {
+    /// Commits `paths`, or every change (new files included) when `paths` is empty.
+    fn commit(&self, message: &str, paths: &[String], no_verify: bool) -> Result<(), String>;
+    /// Folds `paths`, or every change, into the last commit and keeps its message.
+    fn amend(&self, paths: &[String], no_verify: bool) -> Result<(), String>;
+    /// Discards uncommitted changes to tracked files, leaving paths matching
+    /// the gitignore-style `protected` patterns alone.
+    fn revert(&self, protected: &[String]) -> Result<(), String>;
+    /// Short hash of the commit just made and the `--stat` lines of the files it changed.
+    fn last_commit(&self) -> Result<(String, Vec<String>), String>;
+}
+
+pub struct Git;
+
+impl Git {
+    /// Stages `paths` (or everything) and runs `git commit` with `args`.
+    fn add_and_commit(args: &[&str], paths: &[String], no_verify: bool) -> Result<(), String> {
+        // Paths are relative to where the app runs, git runs from the root.
+        let paths: Vec<PathBuf> = paths
+            .iter()
+            .map(|path| std::path::absolute(path).unwrap_or_else(|_| PathBuf::from(path)))
+            .collect();
+        let mut add = git();
+        add.arg("add");
+        if paths.is_empty() {
+            add.arg("-A");
+        } else {
+            add.arg("--").args(&paths);
+        }
+        run_vcs(&mut add, "git add failed")?;
+
+        let mut commit = git();
+        commit.arg("commit").args(args);
+        if no_verify {
+            commit.arg("--no-verify");
+        }
+        if !paths.is_empty() {
+            // Leave anything else already in the index out of this commit.
+            commit.arg("--").args(&paths);
+        }
+        run_vcs(&mut commit, "git commit failed").map(|_| ())
+    }
+}
+
+impl Vcs for Git {
+    fn commit(&self, message: &str, paths: &[String], no_verify: bool) -> Result<(), String> {
+        Git::add_and_commit(&["-m", message], paths, no_verify)
+    }
+
+    fn amend(&self, paths: &[String], no_verify: bool) -> Result<(), String> {
+        // Checked again here: the tests ran in between.
+        if let Some(reason) = amend_refusal() {
+            return Err(reason);
+        }
+        Git::add_and_commit(&["--amend", "--no-edit"], paths, no_verify)
+    }
+
+    fn revert(&self, protected: &[String]) -> Result<(), String> {
+        let mut restore = git();
+        restore.args(["restore", "--", "."]);
+        restore.args(protected.iter().map(|pattern| exclude_pathspec(pattern)));
+        run_vcs(&mut restore, "git restore failed").map(|_| ())
+    }
+
+    fn last_commit(&self) -> Result<(String, Vec<String>), String> {
+        let hash = run_vcs(
+            git().args(["rev-parse", "--short", "HEAD"]),
+            "git rev-parse failed",
+        )?;
+        let hash = hash.trim().to_string();
+        let stat = diff_stat(&hash)?;
+        Ok((hash, stat))
+    }
+}
+
+/// Jujutsu (`jj`). It has no hooks to skip, so `no_verify` does nothing.
+pub struct Jujutsu;
+
+impl Vcs for Jujutsu {
+    fn commit(&self, message: &str, paths: &[String], _no_verify: bool) -> Result<(), String> {
+        // The working copy is a commit already, new files included.
+        let mut commit = Command::new("jj");
+        commit.args(["commit", "-m", message]).args(paths);
+        run_vcs(&mut commit, "jj commit failed").map(|_| ())
+    }
+
+    fn amend(&self, _paths: &[String], _no_verify: bool) -> Result<(), String> {
+        Err("amending a TCR commit needs vcs = \"git\"".to_string())
+    }
+
+    fn revert(&self, protected: &[String]) -> Result<(), String> {
+        let mut restore = Command::new("jj");
+        restore.arg("restore");
+        if !protected.is_empty() {
+            let kept: Vec<String> = protected
+                .iter()
+                .map(|pattern| format!("root-glob:{:?}", ignore_glob(pattern)))
+                .collect();
+            restore.arg(format!("~({})", kept.join(" | ")));
+        }
+        run_vcs(&mut restore, "jj restore failed").map(|_| ())
+    }
+
+    fn last_commit(&self) -> Result<(String, Vec<String>), String> {
+        // `jj commit` leaves a new empty change on top of the one it made.
+        let hash = run_vcs(
+            Command::new("jj").args(["log", "--no-graph", "-r", "@-", "-T", "commit_id.short()"]),
+            "jj log failed",
+        )?;
+        let stat = run_vcs(
+            Command::new("jj").args(["diff", "--stat", "-r", "@-"]),
+            "jj diff failed",
+        )?;
+        Ok((
+            hash.trim().to_string(),
+            stat.lines().map(str::to_string).collect(),
+        ))
+    }
+}
+
+/// Mercurial (`hg`). `no_verify` is git-only; hg hooks always run.
+pub struct Mercurial;
+
+impl Vcs for Mercurial {
+    fn commit(&self, message: &str, paths: &[String], _no_verify: bool) -> Result<(), String> {
+        // `-A` adds new files and forgets deleted ones, like `git add -A`.
+        let mut commit = Command::new("hg");
+        commit.args(["commit", "-A", "-m", message]).args(paths);
+        run_vcs(&mut commit, "hg commit failed").map(|_| ())
+    }
+
+    fn amend(&self, _paths: &[String], _no_verify: bool) -> Result<(), String> {
+        Err("amending a TCR commit needs vcs = \"git\"".to_string())
+    }
+
+    fn revert(&self, protected: &[String]) -> Result<(), String> {
+        let mut revert = Command::new("hg");
+        revert.args(["revert", "--all", "--no-backup"]);
+        for pattern in protected {
+            revert
+                .arg("-X")
+                .arg(format!("rootglob:{}", ignore_glob(pattern)));
+        }
+        run_vcs(&mut revert, "hg revert failed").map(|_| ())
+    }
+
+    fn last_commit(&self) -> Result<(String, Vec<String>), String> {
+        let hash = run_vcs(
+            Command::new("hg").args(["log", "-r", ".", "-T", "{node|short}"]),
+            "hg log failed",
+        )?;
+        let stat = run_vcs(
+            Command::new("hg").args(["diff", "--stat", "-c", "."]),
+            "hg diff failed",
+        )?;
+        Ok((
+            hash.trim().to_string(),
+            stat.lines().map(str::to_string).collect(),
+        ))
+    }
+}
+
+/// Runs a version control command without letting its output draw over the
+/// UI and returns its stdout. On failure the first line it printed is
+/// appended to `failure`. A git command that finds `index.lock` taken is
+/// tried again a few times first, as the lock is usually held only briefly.
+fn run_vcs(command: &mut Command, failure: &str) -> Result<String, String> {
+    let mut output = command.output().map_err(|e| e.to_string())?;
+    let mut wait = LOCK_BACKOFF;
+    for _ in 1..LOCK_ATTEMPTS {
+        if output.status.success() || !index_locked(&output) {
+            break;
+        }
+        thread::sleep(wait);
+        wait *= 2;
+        output = command.output().map_err(|e| e.to_string())?;
+    }
+    if output.status.success() {
+        return Ok(String::from_utf8_lossy(&output.stdout).into_owned());
+    }
+    if index_locked(&output) {
+        return Err(format!(
+            "{failure}: git index locked — another git process running?"
+        ));
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match stderr
+        .lines()
+        .chain(stdout.lines())
+        .find(|line| !line.trim().is_empty())
+    {
+        Some(reason) => Err(format!("{failure}: {}", reason.trim())),
+        None => Err(failure.to_string()),
+    }
+}
+
+/// Whether git failed because another process holds `.git/index.lock`.
+fn index_locked(output: &Output) -> bool {
+    String::from_utf8_lossy(&output.stderr).contains("index.lock")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moves_long_commit_subjects_into_the_body() {
+        let message = commit_message("TCR: completed task \"rename the config loader\"", 30);
+        assert_eq!(
+            message,
+            "TCR: completed task \"rename\n\nthe config loader\""
+        );
+        assert_eq!(commit_message("TCR: short", 30), "TCR: short");
+        assert_eq!(commit_message("TCR: any length", 0), "TCR: any length");
+    }
+
+    fn run(exit_code: Option<i32>, output: &[&str]) -> TestRun {
+        TestRun {
+            exit_code,
+            output: output.iter().map(|line| line.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn maps_exit_codes_to_verdicts() {
+        let codes: BTreeMap<String, String> = [("5", "pass"), ("3", "ignore"), ("0", "fail")]
+            .into_iter()
+            .map(|(code, action)| (code.to_string(), action.to_string()))
+            .collect();
+        let verdict = |code| run(code, &[]).verdict(&codes, None, None);
+        assert_eq!(verdict(Some(5)), Verdict::Pass);
+        assert_eq!(
+            verdict(Some(3)),
+            Verdict::Ignore("tests exited with code 3".to_string())
+        );
+        assert_eq!(
+            verdict(Some(0)),
+            Verdict::Fail("tests exited with code 0".to_string())
+        );
+        // Unmapped codes keep their usual meaning.
+        assert_eq!(
+            run(Some(0), &[]).verdict(&BTreeMap::new(), None, None),
+            Verdict::Pass
+        );
+        assert_eq!(
+            verdict(Some(1)),
+            Verdict::Fail("tests exited with code 1".to_string())
+        );
+        // Killed by a signal, or never started.
+        assert_eq!(verdict(None), Verdict::Fail("no exit status".to_string()));
+    }
+
+    #[test]
+    fn checks_output_patterns_only_on_a_passing_run() {
+        let codes = BTreeMap::new();
+        let passed = run(Some(0), &["test a ... ok", "warning: unused variable"]);
+        assert_eq!(
+            passed.verdict(&codes, Some("^error"), Some("test a")),
+            Verdict::Pass
+        );
+        assert_eq!(
+            passed.verdict(&codes, Some("warning:"), None),
+            Verdict::Fail("output matched fail_on_pattern \"warning:\"".to_string())
+        );
+        assert_eq!(
+            passed.verdict(&codes, None, Some("\\d+ passed")),
+            Verdict::Fail("output lacks require_pattern \"\\d+ passed\"".to_string())
+        );
+        // A failing run fails on its exit code whatever the output says.
+        let failed = run(Some(101), &["test a ... ok"]);
+        assert_eq!(
+            failed.verdict(&codes, Some("warning:"), Some("test a")),
+            Verdict::Fail("tests exited with code 101".to_string())
+        );
+    }
+}